@@ -0,0 +1,343 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::notify::command::CommandConfig;
+use crate::notify::desktop::DesktopConfig;
+use crate::notify::email::EmailConfig;
+use crate::notify::gotify::GotifyConfig;
+use crate::notify::matrix::MatrixConfig;
+use crate::notify::mqtt::MqttConfig;
+use crate::notify::ntfy::NtfyConfig;
+use crate::notify::pushover::PushoverConfig;
+use crate::notify::syslog::{JournaldConfig, SyslogConfig};
+use crate::notify::telegram::TelegramConfig;
+use crate::notify::webhook::WebhookConfig;
+use crate::notify::{Notifier, Target};
+
+/// The config file, which is where named notification targets are set up.
+///
+/// ```toml
+/// [[target]]
+/// name = "phone"
+/// kind = "webhook"
+/// url = "https://api.pushcut.io/..."
+/// body = "none"
+///
+/// [[target]]
+/// name = "chat"
+/// kind = "webhook"
+/// url = "https://chat.example.com/hooks/..."
+/// bearer_token_file = "/home/me/.chat-token"
+/// required = false
+///
+/// [[target]]
+/// name = "ntfy"
+/// kind = "ntfy"
+/// url = "https://ntfy.example.com/builds"
+/// tags = ["computer"]
+///
+/// [[target]]
+/// name = "gotify"
+/// kind = "gotify"
+/// url = "https://gotify.example.com"
+/// token_env = "GOTIFY_TOKEN"
+///
+/// [[target]]
+/// name = "pushover"
+/// kind = "pushover"
+/// token_env = "PUSHOVER_TOKEN"
+/// user_env = "PUSHOVER_USER"
+/// emergency_on_crash = true
+///
+/// [[target]]
+/// name = "telegram"
+/// kind = "telegram"
+/// token_env = "TELEGRAM_BOT_TOKEN"
+/// chat_id = "123456789"
+///
+/// [[target]]
+/// name = "on-call"
+/// kind = "matrix"
+/// homeserver = "https://matrix.example.com"
+/// room_id = "!abc123:example.com"
+/// access_token_env = "MATRIX_ACCESS_TOKEN"
+///
+/// [[target]]
+/// name = "email"
+/// kind = "email"
+/// host = "smtp.example.com"
+/// username = "me@example.com"
+/// password_env = "SMTP_PASSWORD"
+/// to = ["me@example.com"]
+///
+/// [[target]]
+/// name = "desktop"
+/// kind = "desktop"
+/// required = false
+///
+/// [[target]]
+/// name = "log"
+/// kind = "command"
+/// command = ["/usr/local/bin/log-stop-event", "--verbose"]
+///
+/// [[target]]
+/// name = "home-assistant"
+/// kind = "mqtt"
+/// url = "mqtts://broker.example.com"
+/// topic = "lab/notif_stopped"
+/// username = "lab"
+/// password_env = "MQTT_PASSWORD"
+///
+/// [[target]]
+/// name = "audit"
+/// kind = "journald"
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "target")]
+    pub targets: Vec<TargetConfig>,
+}
+
+/// A target in the config file.
+///
+/// This is read from a table by hand, rather than flattening `kind` into it, since serde ignores
+/// unknown keys next to a flattened field (so a typo like `requried = false` would be missed).
+#[derive(Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct TargetConfig {
+    pub name: String,
+    /// Whether failing to notify this target makes the run fail.
+    pub required: bool,
+    pub kind: TargetKind,
+}
+
+/// The kind of target, along with its options.
+pub enum TargetKind {
+    Webhook(WebhookConfig),
+    Ntfy(NtfyConfig),
+    Gotify(GotifyConfig),
+    Pushover(PushoverConfig),
+    Telegram(TelegramConfig),
+    Matrix(MatrixConfig),
+    Email(EmailConfig),
+    Desktop(DesktopConfig),
+    Command(CommandConfig),
+    Mqtt(MqttConfig),
+    Journald(JournaldConfig),
+    Syslog(SyslogConfig),
+}
+
+impl TryFrom<toml::Table> for TargetConfig {
+    type Error = String;
+
+    fn try_from(mut table: toml::Table) -> Result<Self, String> {
+        let name = match table.remove("name") {
+            Some(toml::Value::String(name)) => name,
+            Some(_) => return Err("a target's `name` must be a string".to_owned()),
+            None => return Err("a target is missing its `name`".to_owned()),
+        };
+        let required = match table.remove("required") {
+            Some(toml::Value::Boolean(required)) => required,
+            Some(_) => return Err(format!("{name}: `required` must be true or false")),
+            None => true,
+        };
+        let kind = match table.remove("kind") {
+            Some(toml::Value::String(kind)) => kind,
+            Some(_) => return Err(format!("{name}: `kind` must be a string")),
+            None => return Err(format!("{name}: missing `kind`")),
+        };
+
+        // The rest are the options for the kind of target, which reject any they don't know.
+        fn options<T: serde::de::DeserializeOwned>(table: toml::Table) -> Result<T, String> {
+            T::deserialize(toml::Value::Table(table)).map_err(|e| e.message().to_owned())
+        }
+        let kind = match kind.as_str() {
+            "webhook" => options(table).map(TargetKind::Webhook),
+            "ntfy" => options(table).map(TargetKind::Ntfy),
+            "gotify" => options(table).map(TargetKind::Gotify),
+            "pushover" => options(table).map(TargetKind::Pushover),
+            "telegram" => options(table).map(TargetKind::Telegram),
+            "matrix" => options(table).map(TargetKind::Matrix),
+            "email" => options(table).map(TargetKind::Email),
+            "desktop" => options(table).map(TargetKind::Desktop),
+            "command" => options(table).map(TargetKind::Command),
+            "mqtt" => options(table).map(TargetKind::Mqtt),
+            "journald" => options(table).map(TargetKind::Journald),
+            "syslog" => options(table).map(TargetKind::Syslog),
+            _ => Err(format!(
+                "unknown kind `{kind}` (expected one of: webhook, ntfy, gotify, pushover, \
+                 telegram, matrix, email, desktop, command, mqtt, journald, syslog)"
+            )),
+        }
+        .map_err(|e| format!("{name}: {e}"))?;
+
+        Ok(Self {
+            name,
+            required,
+            kind,
+        })
+    }
+}
+
+impl TargetConfig {
+    pub fn build(self) -> Result<Target, String> {
+        let name = self.name;
+        let notifier = match self.kind {
+            TargetKind::Webhook(config) => config.build().map(Notifier::Webhook),
+            TargetKind::Ntfy(config) => config.build().map(Notifier::Ntfy),
+            TargetKind::Gotify(config) => config.build().map(Notifier::Gotify),
+            TargetKind::Pushover(config) => config.build().map(Notifier::Pushover),
+            TargetKind::Telegram(config) => config.build().map(Notifier::Telegram),
+            TargetKind::Matrix(config) => config.build().map(Notifier::Matrix),
+            TargetKind::Email(config) => config.build().map(Notifier::Email),
+            TargetKind::Desktop(config) => config.build().map(Notifier::Desktop),
+            TargetKind::Command(config) => config.build().map(Notifier::Command),
+            TargetKind::Mqtt(config) => config.build().map(Notifier::Mqtt),
+            TargetKind::Journald(config) => config.build().map(Notifier::Journald),
+            TargetKind::Syslog(config) => config.build().map(Notifier::Syslog),
+        }
+        .map_err(|e| format!("{name}: {e}"))?;
+
+        Ok(Target {
+            name,
+            required: self.required,
+            notifier,
+        })
+    }
+}
+
+impl Config {
+    /// Loads the config file from `path`, or from the default location if there's no path. It's
+    /// fine for there not to be a file in the default location.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, must_exist) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !must_exist && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+        };
+        Self::parse(&text).map_err(|e| format!("invalid config file {}: {e}", path.display()))
+    }
+
+    /// Parses the text of a config file, making sure that the targets have different names.
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (i, target) in config.targets.iter().enumerate() {
+            if config.targets[..i].iter().any(|t| t.name == target.name) {
+                return Err(format!(
+                    "there's more than one target named {}",
+                    target.name
+                ));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/notif_stopped/config.toml` (`~/.config` by default, or `%APPDATA%` on
+/// Windows).
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(default_config_dir)?;
+    Some(config_dir.join("notif_stopped").join("config.toml"))
+}
+
+#[cfg(windows)]
+fn default_config_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn default_config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example in `Config`'s documentation.
+    fn documented_example() -> String {
+        let source = include_str!("config.rs");
+        let (_, example) = source.split_once("/// ```toml\n").unwrap();
+        let (example, _) = example.split_once("/// ```\n").unwrap();
+        example
+            .lines()
+            .map(|line| line.trim_start_matches("///").trim_start())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn documented_example_parses() {
+        let config = Config::parse(&documented_example()).unwrap();
+        let names: Vec<&str> = config.targets.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "phone",
+                "chat",
+                "ntfy",
+                "gotify",
+                "pushover",
+                "telegram",
+                "on-call",
+                "email",
+                "desktop",
+                "log",
+                "home-assistant",
+                "audit"
+            ]
+        );
+        assert!(config.targets[0].required);
+        assert!(!config.targets[1].required);
+        assert!(matches!(config.targets[6].kind, TargetKind::Matrix(_)));
+        assert!(matches!(config.targets[11].kind, TargetKind::Journald(_)));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for (typo, expected) in [
+            ("requried = false", "phone: unknown field `requried`"),
+            ("bodyy = \"none\"", "phone: unknown field `bodyy`"),
+        ] {
+            let text = format!(
+                "[[target]]\nname = \"phone\"\nkind = \"webhook\"\nurl = \"https://example.com\"\n{typo}\n"
+            );
+            let result = Config::parse(&text);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(expected)),
+                "{typo}: {:?}",
+                result.err()
+            );
+        }
+
+        let result = Config::parse("[[target]]\nname = \"x\"\nkind = \"pager\"\n");
+        assert!(result.is_err_and(|e| e.contains("x: unknown kind `pager`")));
+        let result = Config::parse("[[targets]]\nname = \"x\"\nkind = \"desktop\"\n");
+        assert!(result.is_err_and(|e| e.contains("unknown field `targets`")));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let text = "[[target]]\nname = \"a\"\nkind = \"desktop\"\n\n\
+                    [[target]]\nname = \"a\"\nkind = \"journald\"\n";
+        assert_eq!(
+            Config::parse(text).err(),
+            Some("there's more than one target named a".to_owned())
+        );
+    }
+}
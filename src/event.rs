@@ -0,0 +1,344 @@
+use std::fmt;
+use std::process::{ExitCode, ExitStatus};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::launch::Finished;
+use crate::process::ProcessInfo;
+
+/// The names of the fields in an event's JSON representation.
+pub const JSON_FIELDS: &[&str] = &[
+    "description",
+    "process_name",
+    "pid",
+    "command_line",
+    "hostname",
+    "started_at",
+    "stopped_at",
+    "runtime_secs",
+    "exit_reason",
+    "exit_code",
+    "signal",
+    "core_dumped",
+];
+
+/// Everything that's known about a process having stopped, which notifications are built from.
+///
+/// This is (de)serialized to save undelivered notifications in the outbox.
+#[derive(Serialize, Deserialize)]
+pub struct StopEvent {
+    /// A short description of what stopped, e.g. `make (pid 123) exited with code 0`.
+    pub description: String,
+    pub process_name: String,
+    /// This is `None` if several processes were watched (and likewise for the command line).
+    pub pid: Option<u32>,
+    pub command_line: Option<Vec<String>>,
+    pub hostname: String,
+    pub started_at: Option<SystemTime>,
+    pub stopped_at: SystemTime,
+    pub exit_reason: ExitReason,
+}
+
+impl StopEvent {
+    /// An event for watched processes that have exited.
+    pub fn for_processes(exited: &[ProcessInfo], description: String, reason: ExitReason) -> Self {
+        let mut names: Vec<&str> = exited.iter().map(|p| p.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        let single = match exited {
+            [process] => Some(process),
+            _ => None,
+        };
+
+        Self {
+            description,
+            process_name: names.join(", "),
+            pid: single.map(|p| p.pid.as_u32()),
+            command_line: single.map(|p| p.cmd.clone()),
+            hostname: hostname(),
+            started_at: exited
+                .iter()
+                .map(|p| SystemTime::UNIX_EPOCH + Duration::from_secs(p.start_time))
+                .min(),
+            stopped_at: SystemTime::now(),
+            exit_reason: reason,
+        }
+    }
+
+    /// An event for a command that was run to completion.
+    pub fn for_command(command: &[String], finished: &Finished) -> Self {
+        let reason = ExitReason::from_status(finished.status);
+        let runtime = Duration::from_secs(finished.runtime.as_secs());
+        let description = format!(
+            "`{}` {reason} after {}",
+            command.join(" "),
+            humantime::format_duration(runtime)
+        );
+
+        Self {
+            description,
+            process_name: command[0].clone(),
+            pid: Some(finished.pid),
+            command_line: Some(command.to_vec()),
+            hostname: hostname(),
+            started_at: Some(finished.started_at),
+            stopped_at: finished.started_at + finished.runtime,
+            exit_reason: reason,
+        }
+    }
+
+    /// How long the process ran for, if known.
+    pub fn runtime(&self) -> Option<Duration> {
+        let started_at = self.started_at?;
+        self.stopped_at.duration_since(started_at).ok()
+    }
+
+    /// Whether the process succeeded (exited with code 0), if that's known.
+    pub fn succeeded(&self) -> Option<bool> {
+        match self.exit_reason {
+            ExitReason::Exited(code) => Some(code == 0),
+            ExitReason::Signaled { .. } => Some(false),
+            ExitReason::Unknown => None,
+        }
+    }
+
+    /// A short title for notifications, e.g. `make stopped on build-server`.
+    pub fn title(&self) -> String {
+        format!("{} stopped on {}", self.process_name, self.hostname)
+    }
+
+    /// How long the process ran for, rounded to the second, e.g. `1h 2m 3s`.
+    pub fn duration(&self) -> Option<String> {
+        self.runtime()
+            .map(|d| humantime::format_duration(Duration::from_secs(d.as_secs())).to_string())
+    }
+
+    /// Everything that's known besides the description, as labelled values, for notifications
+    /// that are read by people.
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = vec![("Host", self.hostname.clone())];
+        if let Some(pid) = self.pid {
+            details.push(("PID", pid.to_string()));
+        }
+        if let Some(command_line) = &self.command_line {
+            details.push(("Command", command_line.join(" ")));
+        }
+        if let Some(started_at) = self.started_at {
+            let started_at = humantime::format_rfc3339_seconds(started_at).to_string();
+            details.push(("Started", started_at));
+        }
+        let stopped_at = humantime::format_rfc3339_seconds(self.stopped_at).to_string();
+        details.push(("Stopped", stopped_at));
+        if let Some(duration) = self.duration() {
+            details.push(("Runtime", duration));
+        }
+        details
+    }
+
+    /// A plain-text report with the description and then the details, one per line.
+    pub fn report(&self) -> String {
+        let mut report = self.description.clone();
+        for (label, value) in self.details() {
+            report.push_str(&format!("\n{label}: {value}"));
+        }
+        report
+    }
+
+    /// The JSON representation of the event, which is sent as the webhook's body.
+    pub fn to_json(&self) -> serde_json::Value {
+        let (exit_code, signal, core_dumped) = match self.exit_reason {
+            ExitReason::Exited(code) => (Some(code), None, false),
+            ExitReason::Signaled {
+                signal,
+                core_dumped,
+            } => (None, Some(signal), core_dumped),
+            ExitReason::Unknown => (None, None, false),
+        };
+
+        json!({
+            "description": self.description,
+            "process_name": self.process_name,
+            "pid": self.pid,
+            "command_line": self.command_line,
+            "hostname": self.hostname,
+            "started_at": self.started_at.map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+            "stopped_at": humantime::format_rfc3339_seconds(self.stopped_at).to_string(),
+            "runtime_secs": self.runtime().map(|d| d.as_secs()),
+            "exit_reason": self.exit_reason.to_string(),
+            "exit_code": exit_code,
+            "signal": signal,
+            "core_dumped": core_dumped,
+        })
+    }
+
+    /// The fields of the JSON representation as strings, for plain-text formats. Unknown values are
+    /// `None`, and the command line is joined with spaces.
+    pub fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        let json = self.to_json();
+        JSON_FIELDS
+            .iter()
+            .map(|&key| {
+                let value = match &json[key] {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Array(items) => Some(
+                        items
+                            .iter()
+                            .map(|item| {
+                                item.as_str()
+                                    .map_or_else(|| item.to_string(), str::to_owned)
+                            })
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                    value => Some(value.to_string()),
+                };
+                (key, value)
+            })
+            .collect()
+    }
+
+    /// An event with fixed details, for tests.
+    #[cfg(test)]
+    pub fn example(exit_reason: ExitReason) -> Self {
+        let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        Self {
+            description: format!("make (pid 4242) {exit_reason}"),
+            process_name: "make".to_owned(),
+            pid: Some(4242),
+            command_line: Some(vec!["make".to_owned(), "-j".to_owned(), "8".to_owned()]),
+            hostname: "host".to_owned(),
+            started_at: Some(started_at),
+            stopped_at: started_at + Duration::from_secs(90),
+            exit_reason,
+        }
+    }
+}
+
+fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// How a process stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitReason {
+    /// The process exited normally with an exit code.
+    Exited(i32),
+    /// The process was terminated by a signal.
+    Signaled { signal: i32, core_dumped: bool },
+    /// The process stopped, but there's no way to know how (e.g. it wasn't our child).
+    Unknown,
+}
+
+impl ExitReason {
+    pub fn from_status(status: ExitStatus) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            if let Some(signal) = status.signal() {
+                return Self::Signaled {
+                    signal,
+                    core_dumped: status.core_dumped(),
+                };
+            }
+        }
+
+        status.code().map_or(Self::Unknown, Self::Exited)
+    }
+
+    /// The exit code a shell would give for this, i.e. the exit code or 128 plus the signal.
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            Self::Exited(code) => ExitCode::from(code as u8),
+            Self::Signaled { signal, .. } => ExitCode::from((128 + signal) as u8),
+            Self::Unknown => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Exited(code) => write!(f, "exited with code {code}"),
+            Self::Signaled {
+                signal,
+                core_dumped,
+            } => {
+                write!(f, "killed by signal {signal}")?;
+                if let Some(name) = signal_name(signal) {
+                    write!(f, " ({name})")?;
+                }
+                if core_dumped {
+                    write!(f, ", core dumped")?;
+                }
+                Ok(())
+            }
+            Self::Unknown => write!(f, "exited"),
+        }
+    }
+}
+
+/// The name of a signal, for the common ones.
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    })
+}
+
+#[cfg(not(unix))]
+fn signal_name(_signal: i32) -> Option<&'static str> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_as_strings() {
+        let event = StopEvent::example(ExitReason::Signaled {
+            signal: 9,
+            core_dumped: false,
+        });
+        let fields = event.fields();
+        let field = |key| {
+            fields
+                .iter()
+                .find(|(name, _)| *name == key)
+                .unwrap()
+                .1
+                .as_deref()
+        };
+
+        assert_eq!(fields.len(), JSON_FIELDS.len());
+        assert_eq!(field("pid"), Some("4242"));
+        assert_eq!(field("command_line"), Some("make -j 8"));
+        assert_eq!(field("started_at"), Some("2023-11-14T22:13:20Z"));
+        assert_eq!(field("runtime_secs"), Some("90"));
+        assert_eq!(field("exit_code"), None);
+        assert_eq!(field("signal"), Some("9"));
+        assert_eq!(field("core_dumped"), Some("false"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A command that has been run to completion.
+pub struct Finished {
+    pub pid: u32,
+    pub status: ExitStatus,
+    pub started_at: SystemTime,
+    /// How long the command ran for (measured with a monotonic clock).
+    pub runtime: Duration,
+}
+
+/// Runs a command to completion.
+///
+/// The command inherits stdio. On unix, signals sent to this process by other processes are
+/// forwarded to the command (signals from the terminal already reach it since it's in the same
+/// process group).
+pub fn run_command(command: &[String]) -> Result<Finished, String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| "no command given".to_owned())?;
+
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+    let mut child = Command::new(program)
+        .args(args)
+        .spawn()
+        .map_err(|e| format!("failed to run `{program}`: {e}"))?;
+
+    #[cfg(unix)]
+    let previous = signals::forward_to(child.id());
+    let status = child.wait();
+    #[cfg(unix)]
+    signals::stop_forwarding(previous);
+
+    let status = status.map_err(|e| format!("failed to wait on `{program}`: {e}"))?;
+    Ok(Finished {
+        pid: child.id(),
+        status,
+        started_at,
+        runtime: start.elapsed(),
+    })
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The signals that get forwarded to the child.
+    const FORWARDED: [libc::c_int; 4] = [libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGTERM];
+
+    /// The pid of the child to forward signals to, or `0` if there isn't one.
+    static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+
+    extern "C" fn forward(
+        signal: libc::c_int,
+        info: *mut libc::siginfo_t,
+        _context: *mut libc::c_void,
+    ) {
+        // Signals sent by a process (with `kill` and friends) have a non-positive `si_code`. The
+        // rest come from the kernel, e.g. the terminal's ^C, which the child already receives.
+        // SAFETY: the kernel always passes a valid `siginfo_t` to `SA_SIGINFO` handlers.
+        let from_process = unsafe { (*info).si_code } <= 0;
+        let pid = CHILD_PID.load(Ordering::SeqCst);
+        if from_process && pid > 0 {
+            // SAFETY: `kill` is async-signal-safe.
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+
+    /// How the forwarded signals were handled before, e.g. `SIGHUP` being ignored under `nohup`.
+    pub struct Previous([libc::sigaction; FORWARDED.len()]);
+
+    /// Starts forwarding signals to `pid`, keeping them from terminating this process.
+    pub fn forward_to(pid: u32) -> Previous {
+        CHILD_PID.store(pid as i32, Ordering::SeqCst);
+        // SAFETY: an all-zero `sigaction` is valid (and these are overwritten below anyway).
+        let mut previous: [libc::sigaction; FORWARDED.len()] = unsafe { std::mem::zeroed() };
+        for (signal, previous) in FORWARDED.into_iter().zip(&mut previous) {
+            // SAFETY: `forward` only does async-signal-safe things, and the `sigaction` struct is
+            // fully initialized.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, previous);
+            }
+        }
+        Previous(previous)
+    }
+
+    /// Stops forwarding signals, restoring how they were handled before.
+    pub fn stop_forwarding(previous: Previous) {
+        CHILD_PID.store(0, Ordering::SeqCst);
+        for (signal, previous) in FORWARDED.into_iter().zip(&previous.0) {
+            // SAFETY: `previous` was filled in by `sigaction` itself.
+            unsafe { libc::sigaction(signal, previous, std::ptr::null_mut()) };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The handler for `signal`.
+        fn handler(signal: libc::c_int) -> libc::sighandler_t {
+            // SAFETY: `sigaction` only writes to the struct, which may be zeroed.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(signal, std::ptr::null(), &mut action);
+                action.sa_sigaction
+            }
+        }
+
+        #[test]
+        fn previous_handlers_are_restored() {
+            // SAFETY: nothing in the tests sends `SIGHUP`, and it's ignored like under `nohup`.
+            let original = unsafe { libc::signal(libc::SIGHUP, libc::SIG_IGN) };
+
+            let previous = forward_to(0);
+            assert_eq!(
+                handler(libc::SIGHUP),
+                forward as *const () as libc::sighandler_t
+            );
+            stop_forwarding(previous);
+            assert_eq!(handler(libc::SIGHUP), libc::SIG_IGN);
+            assert_eq!(handler(libc::SIGTERM), libc::SIG_DFL);
+
+            // SAFETY: as above.
+            unsafe { libc::signal(libc::SIGHUP, original) };
+        }
+    }
+}
@@ -1,11 +1,40 @@
+mod config;
+mod event;
+mod launch;
+mod notify;
+mod outbox;
+mod process;
+mod template;
+
+use std::path::PathBuf;
 use std::process::ExitCode;
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
+use regex::Regex;
+use sysinfo::Pid;
+
+use config::Config;
+use event::{ExitReason, StopEvent};
+use notify::command::CommandConfig;
+use notify::desktop::DesktopConfig;
+use notify::email::{EmailConfig, Security};
+use notify::gotify::GotifyConfig;
+use notify::matrix::MatrixConfig;
+use notify::mqtt::MqttConfig;
+use notify::ntfy::NtfyConfig;
+use notify::pushover::PushoverConfig;
+use notify::syslog::{JournaldConfig, SyslogConfig};
+use notify::telegram::TelegramConfig;
+use notify::webhook::{Body, Method, WebhookConfig};
+use notify::{Notifier, Retry, Target};
+use outbox::{Flushed, Outbox, PendingTarget};
+use process::{block_while_processes_running, Selector, Until};
+use template::Template;
 
 fn main() -> ExitCode {
     match run() {
-        Ok(_) => ExitCode::SUCCESS,
+        Ok(code) => code,
         Err(e) => {
             eprintln!("\x1b[91merror\x1b[0m: {e}");
             ExitCode::FAILURE
@@ -13,74 +42,792 @@ fn main() -> ExitCode {
     }
 }
 
-/// Run the program.
-fn run() -> Result<(), String> {
+/// Run the program, returning the exit code to use.
+fn run() -> Result<ExitCode, String> {
+    // This has to happen first so that options can be set in a .env file.
+    load_dotenv()?;
     let cli = Cli::parse();
     cli.enforce_invariants()?;
-    let interval = std::time::Duration::from_secs(cli.interval);
-    // This'll be `None` if it's a dry run and `Some` if it isn't.
-    let maybe_url = (!cli.dry_run).then(get_webhook_url).transpose()?;
+    let interval = Duration::from_secs(cli.interval);
+    let targets = cli.targets()?;
+    if targets.is_empty() && !cli.dry_run {
+        return Err(
+            "no notification targets: 'NOTIF_URL' environment variable needs to be set (to the \
+             webhook url), or targets need to be added with their options (like `--url` or \
+             `--ntfy`) or in the config file"
+                .to_owned(),
+        );
+    }
+    // Without any targets, a dry run still shows the body that would be sent.
+    let dry_run_template = match (cli.dry_run && targets.is_empty(), &cli.template) {
+        (true, Some(path)) => Some(Template::load(path)?),
+        _ => None,
+    };
+    let retry = cli.retry();
+    let outbox = Outbox::open()?;
+
+    if let Some(Command::Flush) = cli.command {
+        return flush(&outbox, &targets, retry, cli.dry_run);
+    }
 
-    if !block_while_process_running(&cli.process_name, interval) {
-        return Err(format!("process isn't running: {}", cli.process_name));
+    // Opportunistically send anything that couldn't be sent before, without holding things up
+    // (or failing) if it still can't be.
+    if !cli.dry_run {
+        match flush_outbox(&outbox, &targets, retry.once()) {
+            Ok(Flushed { sent: 0, .. }) => (),
+            Ok(Flushed { sent, .. }) => println!("Sent {sent} saved notification(s)"),
+            Err(e) => eprintln!("\x1b[93mwarning\x1b[0m: {e}"),
+        }
     }
 
+    let (event, code) = match &cli.command {
+        Some(Command::Run { command }) => {
+            let finished = launch::run_command(command)?;
+            let event = StopEvent::for_command(command, &finished);
+            let code = event.exit_reason.exit_code();
+            (event, code)
+        }
+        Some(Command::Flush) => unreachable!(),
+        #[cfg(target_os = "linux")]
+        None if cli.trace => {
+            let process = cli.selector().find_process()?;
+            let reason = process::trace_until_exit(&process)?;
+            let description = format!("{} (pid {}) {reason}", process.name, process.pid);
+            let event = StopEvent::for_processes(&[process], description, reason);
+            (event, reason.exit_code())
+        }
+        None => {
+            let selector = cli.selector();
+            let (processes, until) = match cli.until() {
+                Some(until) => (selector.find_processes()?, until),
+                None => (vec![selector.find_process()?], Until::AllExited),
+            };
+            let rescan = cli.rescan.then_some(&selector);
+            let summary = block_while_processes_running(processes, until, rescan, interval);
+            let event =
+                StopEvent::for_processes(&summary.exited, summary.describe(), ExitReason::Unknown);
+            (event, ExitCode::SUCCESS)
+        }
+    };
+
     // The process has stopped at this point.
-    if let Some(url) = maybe_url {
-        println!(
-            "Process stopped, sending notification: {}",
-            cli.process_name
+    if cli.dry_run {
+        println!("Process stopped: {}", event.description);
+        if targets.is_empty() {
+            if let Some(body) = cli.body.render(dry_run_template.as_ref(), &event) {
+                println!("{body}");
+            }
+        }
+        for target in &targets {
+            let preview = target.notifier.preview(&event);
+            println!("Would notify {}: {preview}", target.name);
+        }
+        return Ok(code);
+    }
+
+    println!(
+        "Process stopped, sending notification: {}",
+        event.description
+    );
+    let deliveries = notify::notify_all(&targets.iter().collect::<Vec<_>>(), &event, retry);
+    let mut failed = Vec::new();
+    let mut required_failed = false;
+    for delivery in deliveries {
+        let name = &delivery.target.name;
+        match delivery.result {
+            Ok(()) => println!("Notified {name}"),
+            Err(e) if delivery.target.required => {
+                eprintln!("\x1b[91merror\x1b[0m: failed to notify {name}: {e}");
+                required_failed = true;
+                failed.push(PendingTarget::new(delivery.target));
+            }
+            Err(e) => {
+                eprintln!("\x1b[93mwarning\x1b[0m: failed to notify {name}: {e}");
+                failed.push(PendingTarget::new(delivery.target));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        let path = outbox.save(&event, failed)?;
+        eprintln!(
+            "The notification was saved to {} and will be sent on the next run (or with \
+             `notif_stopped flush`)",
+            path.display()
         );
-        minreq::post(url)
-            .send()
-            .map_err(|e| format!("http request failed: {e}"))?;
+    }
+
+    Ok(if required_failed {
+        ExitCode::FAILURE
+    } else {
+        code
+    })
+}
+
+/// Sends (or with a dry run, lists) the notifications in the outbox.
+fn flush(
+    outbox: &Outbox,
+    targets: &[Target],
+    retry: Retry,
+    dry_run: bool,
+) -> Result<ExitCode, String> {
+    if dry_run {
+        for (path, pending) in outbox.entries()? {
+            println!(
+                "{}: {} (for {})",
+                path.display(),
+                pending.event.description,
+                pending
+                    .targets
+                    .iter()
+                    .map(|target| target.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
     } else {
-        println!("Process stopped: {}", cli.process_name);
+        let flushed = flush_outbox(outbox, targets, retry)?;
+        println!("Sent {} saved notification(s)", flushed.sent);
+        if flushed.kept > 0 {
+            println!(
+                "Kept {} saved notification(s) for targets that aren't configured",
+                flushed.kept
+            );
+        }
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+/// Sends the notifications in the outbox to the targets they're still pending for. Targets that
+/// aren't configured in this run are left in the outbox.
+fn flush_outbox(outbox: &Outbox, targets: &[Target], retry: Retry) -> Result<Flushed, String> {
+    let keys: Vec<String> = targets.iter().map(Target::key).collect();
+
+    let result = outbox.flush(|event, pending| {
+        let configured: Vec<&Target> = targets
+            .iter()
+            .zip(&keys)
+            .filter(|(_, key)| pending.iter().any(|p| &p.key == *key))
+            .map(|(target, _)| target)
+            .collect();
+        let mut remaining: Vec<PendingTarget> = pending
+            .iter()
+            .filter(|p| !keys.contains(&p.key))
+            .cloned()
+            .collect();
+
+        let mut errors = Vec::new();
+        for delivery in notify::notify_all(&configured, event, retry) {
+            if let Err(e) = delivery.result {
+                errors.push(format!("{}: {e}", delivery.target.name));
+                remaining.push(PendingTarget::new(delivery.target));
+            }
+        }
+        if errors.is_empty() {
+            Ok(remaining)
+        } else {
+            Err((remaining, errors.join("; ")))
+        }
+    });
+
+    result.map_err(|(sent, e)| {
+        format!("failed to send saved notification ({sent} were sent before it): {e}")
+    })
 }
 
 /// Send a notification to your phone when a program stops running.
 ///
 /// The url for the webhook can be set with the `NOTIF_URL` environment variable (and can be set in
 /// a .env file that's either in the same directory as the exe or in the current working directory).
+/// Several webhooks can be notified at once with `--url` (or `NOTIF_URLS`), other services (like
+/// ntfy or Telegram) have options of their own, and named targets can be set up in a config file
+/// (`~/.config/notif_stopped/config.toml` by default).
 ///
 /// The program must be currently running. This requires an app (on your phone) that will send a
 /// notification when a webhook is POSTed to (such as Pushcut). This can also be used for other,
 /// non-notification webhooks. Note that the process name is needed, not the window title.
+///
+/// The process name and the `--match-*` options can be combined, in which case a process has to
+/// match all of them. Exactly one process must match; if several do, they're listed and `--pid`
+/// (or a narrower match) can be used to pick one of them. Alternatively, `--all` or `--any` can be
+/// used to watch every matching process.
+///
+/// To start a command and get notified once it exits, use `notif_stopped run -- <command>`.
 #[derive(Parser)]
+#[command(group(
+    ArgGroup::new("target")
+        .required(true)
+        .multiple(true)
+        .args(["process_name", "pid", "match_name", "match_exe", "match_cmdline"])
+))]
+#[command(group(ArgGroup::new("all_or_any").args(["all", "any"])))]
+#[command(subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Name of the process to listen for (not the window title)
-    process_name: String,
+    process_name: Option<String>,
+    /// PID of the process to listen for (instead of its name)
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["process_name", "match_name", "match_exe", "match_cmdline"]
+    )]
+    pid: Option<u32>,
+    /// Only match processes whose name matches this regex
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    match_name: Option<Regex>,
+    /// Only match processes whose executable path matches this glob
+    #[arg(long, value_name = "GLOB", value_parser = glob::Pattern::new)]
+    match_exe: Option<glob::Pattern>,
+    /// Only match processes whose command line (arguments joined by spaces) matches this regex
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    match_cmdline: Option<Regex>,
+    /// Watch every matching process and notify once all of them have stopped
+    #[arg(long, conflicts_with = "any")]
+    all: bool,
+    /// Watch every matching process and notify once any of them has stopped
+    #[arg(long)]
+    any: bool,
+    /// Keep looking for new matching processes while waiting (with `--all` or `--any`)
+    #[arg(long, requires = "all_or_any")]
+    rescan: bool,
+    /// Attach to the process with ptrace to find out its exit code (or the signal that killed it)
+    ///
+    /// Its exit code is passed through as this program's exit code, like with `run`. This needs
+    /// permission to trace the process, and can't be used with `--all` or `--any`.
+    #[cfg(target_os = "linux")]
+    #[arg(long, conflicts_with = "all_or_any")]
+    trace: bool,
     // secs
     /// How often to check if it's running (in seconds)
+    ///
+    /// On Linux, exits are noticed right away, so this is only used with `--rescan` or on kernels
+    /// without pidfd support.
     #[arg(short, long, default_value_t = 10)]
     interval: u64,
-    /// Don't send the notification, just print the stopped message & exit
-    #[arg(short, long)]
+    /// Don't send the notification, just print the stopped message (and body) & exit
+    #[arg(short, long, global = true)]
     dry_run: bool,
+    /// Webhook url to notify (can be repeated), instead of `NOTIF_URL`
+    ///
+    /// In the `NOTIF_URLS` environment variable, urls are separated by commas. The other webhook
+    /// options apply to all of these.
+    #[arg(
+        long = "url",
+        value_name = "URL",
+        env = "NOTIF_URLS",
+        value_delimiter = ',',
+        hide_env_values = true,
+        global = true
+    )]
+    urls: Vec<String>,
+    /// ntfy topic url to publish to, e.g. `https://ntfy.sh/my-builds`
+    ///
+    /// An access token can be set with the `NOTIF_NTFY_TOKEN` environment variable.
+    #[arg(
+        long = "ntfy",
+        value_name = "TOPIC_URL",
+        env = "NOTIF_NTFY_URL",
+        hide_env_values = true,
+        global = true
+    )]
+    ntfy_url: Option<String>,
+    /// Gotify server url to send to, e.g. `https://gotify.example.com`
+    ///
+    /// The app token is read from the `NOTIF_GOTIFY_TOKEN` environment variable.
+    #[arg(
+        long = "gotify",
+        value_name = "SERVER_URL",
+        env = "NOTIF_GOTIFY_URL",
+        global = true
+    )]
+    gotify_url: Option<String>,
+    /// Pushover user (or group) key to send to
+    ///
+    /// The app token is read from the `PUSHOVER_TOKEN` environment variable.
+    #[arg(long, env = "PUSHOVER_USER", hide_env_values = true, global = true)]
+    pushover_user: Option<String>,
+    /// Send crashes (processes killed by a signal) to Pushover with emergency priority
+    ///
+    /// Emergency notifications are repeated until they're acknowledged (for up to an hour).
+    #[arg(
+        long,
+        env = "NOTIF_PUSHOVER_EMERGENCY",
+        requires = "pushover_user",
+        global = true
+    )]
+    pushover_emergency: bool,
+    /// Telegram chat id (or `@channel`) for a bot to send to
+    ///
+    /// The bot token is read from the `NOTIF_TELEGRAM_TOKEN` environment variable.
+    #[arg(
+        long,
+        env = "NOTIF_TELEGRAM_CHAT_ID",
+        // Group chat ids are negative.
+        allow_hyphen_values = true,
+        global = true
+    )]
+    telegram_chat_id: Option<String>,
+    /// Telegram Bot API url to use instead of `https://api.telegram.org`
+    #[arg(
+        long,
+        env = "NOTIF_TELEGRAM_API_URL",
+        requires = "telegram_chat_id",
+        global = true
+    )]
+    telegram_api_url: Option<String>,
+    /// Matrix room id (like `!abc123:example.com`) to send to
+    ///
+    /// The access token is read from the `NOTIF_MATRIX_ACCESS_TOKEN` environment variable.
+    #[arg(long, env = "NOTIF_MATRIX_ROOM_ID", global = true)]
+    matrix_room_id: Option<String>,
+    /// Matrix homeserver url to use instead of `https://matrix.org`
+    #[arg(
+        long,
+        env = "NOTIF_MATRIX_HOMESERVER",
+        requires = "matrix_room_id",
+        global = true
+    )]
+    matrix_homeserver: Option<String>,
+    /// Email address to send the notification to (can be repeated), through `--smtp-host`
+    ///
+    /// In the `NOTIF_EMAIL_TO` environment variable, addresses are separated by commas.
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        env = "NOTIF_EMAIL_TO",
+        value_delimiter = ',',
+        requires = "smtp_host",
+        global = true
+    )]
+    email_to: Vec<String>,
+    /// Address to send emails from, instead of `notif_stopped@<host name>`
+    #[arg(long, value_name = "ADDRESS", env = "NOTIF_EMAIL_FROM", global = true)]
+    email_from: Option<String>,
+    /// SMTP server to send emails through
+    #[arg(
+        long,
+        value_name = "HOST",
+        env = "NOTIF_SMTP_HOST",
+        requires = "email_to",
+        global = true
+    )]
+    smtp_host: Option<String>,
+    /// SMTP server port, instead of the usual one for `--smtp-security`
+    #[arg(long, value_name = "PORT", env = "NOTIF_SMTP_PORT", global = true)]
+    smtp_port: Option<u16>,
+    /// How to secure the connection to the SMTP server
+    #[arg(
+        long,
+        value_enum,
+        env = "NOTIF_SMTP_SECURITY",
+        default_value_t = Security::Starttls,
+        global = true
+    )]
+    smtp_security: Security,
+    /// User name to log in to the SMTP server with
+    ///
+    /// The password is read from the `NOTIF_SMTP_PASSWORD` environment variable.
+    #[arg(long, env = "NOTIF_SMTP_USER", global = true)]
+    smtp_user: Option<String>,
+    /// Show a desktop notification (on Linux, through the session bus)
+    ///
+    /// Failures are shown with critical urgency.
+    #[arg(long, env = "NOTIF_DESKTOP", global = true)]
+    desktop: bool,
+    /// Shell command to run with the details of the stopped process
+    ///
+    /// They're in the `NOTIF_PROCESS_NAME`, `NOTIF_PID`, `NOTIF_EXIT_CODE` and
+    /// `NOTIF_DURATION_SECS` environment variables (which are empty when they aren't known), and
+    /// the JSON body is written to its stdin. Exiting with a non-zero code counts as a failure.
+    #[arg(long, value_name = "COMMAND", env = "NOTIF_EXEC", global = true)]
+    exec: Option<String>,
+    /// MQTT broker to publish the JSON body to, as `mqtt://host[:port]` (or `mqtts://` for TLS)
+    ///
+    /// The password for `--mqtt-user` is read from the `NOTIF_MQTT_PASSWORD` environment
+    /// variable.
+    #[arg(long, value_name = "URL", env = "NOTIF_MQTT_URL", global = true)]
+    mqtt_url: Option<String>,
+    /// MQTT topic to publish to
+    #[arg(
+        long,
+        env = "NOTIF_MQTT_TOPIC",
+        default_value = "notif_stopped",
+        global = true
+    )]
+    mqtt_topic: String,
+    /// MQTT quality of service: 0 (at most once), 1 (at least once) or 2 (exactly once)
+    #[arg(
+        long,
+        env = "NOTIF_MQTT_QOS",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(0..=2),
+        global = true
+    )]
+    mqtt_qos: u8,
+    /// Have the MQTT broker keep the message for clients that subscribe later
+    #[arg(long, env = "NOTIF_MQTT_RETAIN", global = true)]
+    mqtt_retain: bool,
+    /// User name to log in to the MQTT broker with
+    #[arg(long, env = "NOTIF_MQTT_USER", requires = "mqtt_url", global = true)]
+    mqtt_user: Option<String>,
+    /// Log the stop event to the systemd journal, with its details in `NOTIF_*` fields
+    #[arg(long, env = "NOTIF_JOURNALD", global = true)]
+    journald: bool,
+    /// Log the stop event to syslog (through `/dev/log`), as an RFC 5424 message
+    #[arg(long, env = "NOTIF_SYSLOG", global = true)]
+    syslog: bool,
+    /// Config file with named notification targets
+    ///
+    /// Defaults to `$XDG_CONFIG_HOME/notif_stopped/config.toml` (`~/.config` if that isn't set),
+    /// and it's fine for that not to exist.
+    #[arg(long, env = "NOTIF_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// What to send as the webhook's body
+    ///
+    /// The JSON body has the process's name, pid, command line, start & stop times, runtime and
+    /// how it exited, as well as the host name. Slack and Discord webhook urls are detected, and
+    /// get a message coloured by whether the process succeeded. Some receivers (like Pushcut) need
+    /// an empty body.
+    #[arg(long, value_enum, default_value_t = Body::Auto, global = true)]
+    body: Body,
+    /// File with a custom webhook body, where placeholders like `{{process_name}}` are filled in
+    ///
+    /// The available placeholders are `{{description}}`, `{{process_name}}`, `{{pid}}`,
+    /// `{{command_line}}`, `{{hostname}}`, `{{started_at}}`, `{{stopped_at}}`, `{{runtime_secs}}`,
+    /// `{{duration}}`, `{{exit_reason}}`, `{{exit_code}}`, `{{signal}}` and `{{core_dumped}}`.
+    /// Templates ending in `.json` are sent as JSON, with values escaped to go in JSON strings and
+    /// unknown values as `null` (a placeholder that's a whole string, like `"{{signal}}"`, becomes
+    /// `null` with its quotes). Others are sent as plain text, with unknown values left empty (a
+    /// `Content-Type` header given with `--header`, in any case, overrides this). With `--dry-run`,
+    /// the rendered body is printed.
+    #[arg(long, env = "NOTIF_TEMPLATE", conflicts_with = "body", global = true)]
+    template: Option<PathBuf>,
+    /// HTTP method to send the webhook with
+    #[arg(long, value_enum, env = "NOTIF_METHOD", default_value_t = Method::Post, global = true)]
+    method: Method,
+    /// Extra header to send with the webhook, as `Name: value` (can be repeated)
+    ///
+    /// In the `NOTIF_HEADERS` environment variable, headers are separated by newlines.
+    #[arg(
+        long = "header",
+        value_name = "HEADER",
+        env = "NOTIF_HEADERS",
+        value_delimiter = '\n',
+        hide_env_values = true,
+        global = true
+    )]
+    headers: Vec<String>,
+    /// File containing a token to send as `Authorization: Bearer <token>`
+    ///
+    /// The token can also be set directly with the `NOTIF_BEARER_TOKEN` environment variable.
+    #[arg(long, env = "NOTIF_BEARER_TOKEN_FILE", global = true)]
+    bearer_token_file: Option<PathBuf>,
+    /// User name for HTTP basic auth
+    ///
+    /// The password is read from the `NOTIF_BASIC_AUTH_PASSWORD` environment variable or from
+    /// `--basic-auth-password-file`.
+    #[arg(
+        long,
+        env = "NOTIF_BASIC_AUTH_USER",
+        conflicts_with = "bearer_token_file",
+        global = true
+    )]
+    basic_auth_user: Option<String>,
+    /// File containing the password for HTTP basic auth
+    #[arg(
+        long,
+        env = "NOTIF_BASIC_AUTH_PASSWORD_FILE",
+        requires = "basic_auth_user",
+        global = true
+    )]
+    basic_auth_password_file: Option<PathBuf>,
+    /// Status code the webhook should respond with (can be repeated), instead of any 2xx status
+    #[arg(
+        long = "expect-status",
+        value_name = "CODE",
+        env = "NOTIF_EXPECT_STATUS",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(i32).range(100..600),
+        global = true
+    )]
+    expected_statuses: Vec<i32>,
+    /// How many times to retry sending a notification if it fails
+    ///
+    /// Only failures that could go away are retried: network errors and timeouts, and responses
+    /// with a 408, 429 or 5xx status. Other responses (like a 404) and commands that exit with a
+    /// non-zero code aren't.
+    #[arg(long, env = "NOTIF_RETRIES", default_value_t = 5, global = true)]
+    retries: u32,
+    // secs
+    /// How long to wait before the first retry (in seconds), which doubles with each retry
+    #[arg(long, env = "NOTIF_RETRY_DELAY", default_value_t = 2, global = true)]
+    retry_delay: u64,
+    // secs
+    /// How long a single attempt at sending a notification can take (in seconds)
+    #[arg(long, env = "NOTIF_TIMEOUT", default_value_t = 30, global = true)]
+    timeout: u64,
+    // secs
+    /// How long sending a notification can take in total, including retries (in seconds)
+    #[arg(long, env = "NOTIF_DEADLINE", default_value_t = 300, global = true)]
+    deadline: u64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a command and send a notification when it exits
+    ///
+    /// The command's exit code is passed through as this program's exit code.
+    Run {
+        /// The command to run, followed by its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Send the notifications that couldn't be delivered before
+    ///
+    /// They're sent in order, with their original details (and times). This also happens at the
+    /// start of every run. With `--dry-run`, they're just listed.
+    Flush,
 }
 
 impl Cli {
     fn enforce_invariants(&self) -> Result<(), String> {
-        if self.process_name.is_empty() {
+        if self.process_name.as_deref() == Some("") {
             return Err("process name can't be empty".to_owned());
         }
 
+        let watching = self.process_name.is_some()
+            || self.pid.is_some()
+            || self.match_name.is_some()
+            || self.match_exe.is_some()
+            || self.match_cmdline.is_some();
+        if self.command.is_some() && watching {
+            return Err("can't watch an existing process and use a subcommand at once".to_owned());
+        }
+
+        if self.timeout < 1 {
+            return Err("timeout is too short (must be at least 1 second)".to_owned());
+        }
+
         if self.interval < 1 {
             Err("interval is too short (must be at least 1 second)".to_owned())
         } else {
             Ok(())
         }
     }
+
+    /// When to stop waiting, if every matching process should be watched.
+    fn until(&self) -> Option<Until> {
+        if self.all {
+            Some(Until::AllExited)
+        } else if self.any {
+            Some(Until::AnyExited)
+        } else {
+            None
+        }
+    }
+
+    /// How hard to try to deliver notifications.
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            initial_delay: Duration::from_secs(self.retry_delay),
+            timeout: Duration::from_secs(self.timeout),
+            deadline: Duration::from_secs(self.deadline),
+        }
+    }
+
+    /// The targets to notify: the ones from the command line (or environment) followed by the ones
+    /// in the config file.
+    fn targets(&self) -> Result<Vec<Target>, String> {
+        let urls = self.webhook_urls();
+        let mut targets = Vec::new();
+
+        for (i, url) in urls.iter().enumerate() {
+            let name = match urls.len() {
+                1 => "webhook".to_owned(),
+                _ => format!("webhook {}", i + 1),
+            };
+            let webhook = self
+                .webhook_config(url.clone())
+                .build()
+                .map_err(|e| format!("{name}: {e}"))?;
+            targets.push(Target {
+                name,
+                required: true,
+                notifier: Notifier::Webhook(webhook),
+            });
+        }
+
+        if let Some(url) = &self.ntfy_url {
+            let ntfy = NtfyConfig {
+                url: url.clone(),
+                priority: None,
+                tags: Vec::new(),
+                token_file: None,
+                token_env: Some("NOTIF_NTFY_TOKEN".to_owned()),
+            };
+            targets.push(cli_target("ntfy", ntfy.build().map(Notifier::Ntfy))?);
+        }
+        if let Some(url) = &self.gotify_url {
+            let gotify = GotifyConfig {
+                url: url.clone(),
+                token_file: None,
+                token_env: Some("NOTIF_GOTIFY_TOKEN".to_owned()),
+                priority: None,
+                failure_priority: None,
+            };
+            targets.push(cli_target("gotify", gotify.build().map(Notifier::Gotify))?);
+        }
+        if let Some(user) = &self.pushover_user {
+            let pushover = PushoverConfig {
+                token_file: None,
+                token_env: Some("PUSHOVER_TOKEN".to_owned()),
+                user: Some(user.clone()),
+                user_env: None,
+                priority: None,
+                failure_priority: None,
+                emergency_on_crash: self.pushover_emergency,
+                sound: None,
+                api_url: None,
+            };
+            targets.push(cli_target(
+                "pushover",
+                pushover.build().map(Notifier::Pushover),
+            )?);
+        }
+        if let Some(chat_id) = &self.telegram_chat_id {
+            let telegram = TelegramConfig {
+                token_file: None,
+                token_env: Some("NOTIF_TELEGRAM_TOKEN".to_owned()),
+                chat_id: chat_id.clone(),
+                api_url: self.telegram_api_url.clone(),
+            };
+            targets.push(cli_target(
+                "telegram",
+                telegram.build().map(Notifier::Telegram),
+            )?);
+        }
+        if let Some(room_id) = &self.matrix_room_id {
+            let matrix = MatrixConfig {
+                homeserver: self.matrix_homeserver.clone(),
+                room_id: Some(room_id.clone()),
+                room_id_env: None,
+                access_token_file: None,
+                access_token_env: Some("NOTIF_MATRIX_ACCESS_TOKEN".to_owned()),
+            };
+            targets.push(cli_target("matrix", matrix.build().map(Notifier::Matrix))?);
+        }
+        if let Some(host) = &self.smtp_host {
+            let email = EmailConfig {
+                host: host.clone(),
+                port: self.smtp_port,
+                security: self.smtp_security,
+                username: self.smtp_user.clone(),
+                password_file: None,
+                password_env: Some("NOTIF_SMTP_PASSWORD".to_owned()),
+                from: self.email_from.clone(),
+                to: self.email_to.clone(),
+            };
+            targets.push(cli_target("email", email.build().map(Notifier::Email))?);
+        }
+        if self.desktop {
+            let desktop = DesktopConfig::default().build();
+            targets.push(cli_target("desktop", desktop.map(Notifier::Desktop))?);
+        }
+        if let Some(command) = &self.exec {
+            let command = CommandConfig::shell(command).build();
+            targets.push(cli_target("command", command.map(Notifier::Command))?);
+        }
+        if let Some(url) = &self.mqtt_url {
+            let mqtt = MqttConfig {
+                url: url.clone(),
+                topic: self.mqtt_topic.clone(),
+                qos: self.mqtt_qos,
+                retain: self.mqtt_retain,
+                username: self.mqtt_user.clone(),
+                password_file: None,
+                password_env: self
+                    .mqtt_user
+                    .is_some()
+                    .then(|| "NOTIF_MQTT_PASSWORD".to_owned()),
+                client_id: None,
+            };
+            targets.push(cli_target("mqtt", mqtt.build().map(Notifier::Mqtt))?);
+        }
+        if self.journald {
+            let journald = JournaldConfig::default().build();
+            targets.push(cli_target("journald", journald.map(Notifier::Journald))?);
+        }
+        if self.syslog {
+            let syslog = SyslogConfig::default().build();
+            targets.push(cli_target("syslog", syslog.map(Notifier::Syslog))?);
+        }
+
+        for target in Config::load(self.config.as_deref())?.targets {
+            if targets.iter().any(|t| t.name == target.name) {
+                return Err(format!(
+                    "there's more than one target named {}",
+                    target.name
+                ));
+            }
+            targets.push(target.build()?);
+        }
+
+        Ok(targets)
+    }
+
+    /// The webhook urls from `--url`, `NOTIF_URLS` or `NOTIF_URL` (in that order of precedence).
+    fn webhook_urls(&self) -> Vec<String> {
+        let urls: Vec<String> = self
+            .urls
+            .iter()
+            .map(|url| url.trim().to_owned())
+            .filter(|url| !url.is_empty())
+            .collect();
+        if !urls.is_empty() {
+            return urls;
+        }
+
+        match std::env::var("NOTIF_URL") {
+            Ok(url) if !url.is_empty() => vec![url],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The options for a webhook given on the command line.
+    fn webhook_config(&self, url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            method: self.method,
+            headers: self.headers.clone(),
+            bearer_token_file: self.bearer_token_file.clone(),
+            bearer_token_env: Some("NOTIF_BEARER_TOKEN".to_owned()),
+            basic_auth_user: self.basic_auth_user.clone(),
+            basic_auth_password_file: self.basic_auth_password_file.clone(),
+            basic_auth_password_env: Some("NOTIF_BASIC_AUTH_PASSWORD".to_owned()),
+            body: self.body,
+            template: self.template.clone(),
+            expect_status: self.expected_statuses.clone(),
+        }
+    }
+
+    /// The criteria for picking the process to watch.
+    fn selector(&self) -> Selector {
+        Selector {
+            pid: self.pid.map(Pid::from_u32),
+            name: self.process_name.clone(),
+            name_regex: self.match_name.clone(),
+            exe_glob: self.match_exe.clone(),
+            cmdline_regex: self.match_cmdline.clone(),
+        }
+    }
 }
 
-/// Gets the webhook url from a `NOTIF_URL` environment variable (or .env file).
-///
-/// This does basic validation that the url is actually a url. Errors reflect io errors, .env
-/// parsing errors, and invalid pushcut paths.
-fn get_webhook_url() -> Result<String, String> {
+/// Loads environment variables from the .env files in the exe's directory and the current working
+/// directory (if they exist). Variables that are already set aren't overridden.
+fn load_dotenv() -> Result<(), String> {
     let cur_exe = std::env::current_exe().map_err(|e| format!("failed to get current exe: {e}"))?;
     let exe_dir = cur_exe
         .parent()
@@ -101,32 +848,14 @@ fn get_webhook_url() -> Result<String, String> {
         }
     }
 
-    match std::env::var("NOTIF_URL").map_err(|e| e.to_string())? {
-        p if p.is_empty() => {
-            Err("'NOTIF_URL' environment variable needs to be set (to the webhook url)".to_owned())
-        }
-        p if !p.starts_with("http") => Err("`NOTIF_URL` must be a url".to_owned()),
-        p => Ok(p),
-    }
+    Ok(())
 }
 
-/// Blocks while a process with a specified name is running. Returns `false` if the process wasn't
-/// running in the first place.
-///
-/// Whether or not the process is running with be regularly every `check_interval` duration.
-fn block_while_process_running(process_name: &str, check_interval: Duration) -> bool {
-    let mut s = sysinfo::System::new_with_specifics(
-        sysinfo::RefreshKind::new().with_processes(sysinfo::ProcessRefreshKind::everything()),
-    );
-    let pid = match s.processes_by_exact_name(process_name).next() {
-        Some(process) => process.pid(),
-        None => return false,
-    };
-
-    while s.process(pid).is_some() {
-        std::thread::sleep(check_interval);
-        s.refresh_pids(&[pid]);
-    }
-
-    true
+/// A required target given on the command line (or in the environment).
+fn cli_target(name: &str, notifier: Result<Notifier, String>) -> Result<Target, String> {
+    Ok(Target {
+        name: name.to_owned(),
+        required: true,
+        notifier: notifier.map_err(|e| format!("{name}: {e}"))?,
+    })
 }
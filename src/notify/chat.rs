@@ -0,0 +1,167 @@
+use serde_json::json;
+
+use super::{escape_html, truncate};
+use crate::event::StopEvent;
+
+/// Slack's limits on the lengths of section texts and fields, which it rejects messages over.
+const MAX_SLACK_TEXT_CHARS: usize = 3000;
+const MAX_SLACK_FIELD_CHARS: usize = 2000;
+
+/// Discord's limits on the lengths of messages, embed descriptions and embed field values.
+const MAX_DISCORD_CONTENT_CHARS: usize = 2000;
+const MAX_DISCORD_DESCRIPTION_CHARS: usize = 4096;
+const MAX_DISCORD_FIELD_CHARS: usize = 1024;
+
+/// The colours for the outcome of a process: green for success, red for failure and grey when
+/// it isn't known.
+fn colour(event: &StopEvent) -> u32 {
+    match event.succeeded() {
+        Some(true) => 0x2eb886,
+        Some(false) => 0xe01e5a,
+        None => 0x9e9e9e,
+    }
+}
+
+/// A message for a Slack incoming webhook, with the details in a coloured attachment.
+pub fn slack(event: &StopEvent) -> serde_json::Value {
+    let fields: Vec<serde_json::Value> = event
+        .details()
+        .into_iter()
+        .map(|(label, value)| {
+            let max_chars = MAX_SLACK_FIELD_CHARS - label.len() - 3;
+            let text = format!("*{label}*\n{}", slack_escape(&value, max_chars));
+            json!({ "type": "mrkdwn", "text": text })
+        })
+        .collect();
+
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": {
+            "type": "mrkdwn",
+            "text": slack_escape(
+                &format!("*{}*\n{}", event.title(), event.description),
+                MAX_SLACK_TEXT_CHARS,
+            ),
+        },
+    })];
+    // Sections can only have up to 10 fields.
+    for fields in fields.chunks(10) {
+        blocks.push(json!({ "type": "section", "fields": fields }));
+    }
+
+    json!({
+        // This is what shows up in notifications. It's mrkdwn too, so things like `<!channel>`
+        // in a command line have to be escaped here as well.
+        "text": format!("{}: {}", escape_html(&event.title()), escape_html(&event.description)),
+        "attachments": [{
+            "color": format!("#{:06x}", colour(event)),
+            "blocks": blocks,
+        }],
+    })
+}
+
+/// Escapes text for Slack, truncating it so that it's at most `max_chars` chars once it's escaped
+/// (without cutting an escape sequence in half).
+fn slack_escape(text: &str, max_chars: usize) -> String {
+    let mut keep = max_chars;
+    loop {
+        let escaped = escape_html(&truncate(text, keep));
+        match escaped.chars().count().checked_sub(max_chars) {
+            // A char is escaped to at most 5, so a char has to go for every 5 that are over.
+            Some(over @ 1..) => keep = keep.saturating_sub(over.div_ceil(5)),
+            _ => return escaped,
+        }
+    }
+}
+
+/// A message for a Discord webhook, with the details in a coloured embed.
+pub fn discord(event: &StopEvent) -> serde_json::Value {
+    let fields: Vec<serde_json::Value> = event
+        .details()
+        .into_iter()
+        .map(|(label, value)| {
+            let value = truncate(&value, MAX_DISCORD_FIELD_CHARS);
+            json!({ "name": label, "value": value, "inline": true })
+        })
+        .collect();
+
+    json!({
+        "content": truncate(&event.title(), MAX_DISCORD_CONTENT_CHARS),
+        "embeds": [{
+            "description": truncate(&event.description, MAX_DISCORD_DESCRIPTION_CHARS),
+            "color": colour(event),
+            "fields": fields,
+            "timestamp": humantime::format_rfc3339_seconds(event.stopped_at).to_string(),
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+
+    #[test]
+    fn slack_message() {
+        let mut event = StopEvent::example(ExitReason::Exited(1));
+        event.command_line = Some(vec!["echo".to_owned(), "<!channel>".to_owned()]);
+        event.description = "`echo <!channel>` exited with code 1".to_owned();
+        let message = slack(&event);
+
+        assert_eq!(
+            message["text"],
+            "make stopped on host: `echo &lt;!channel&gt;` exited with code 1"
+        );
+        let attachment = &message["attachments"][0];
+        assert_eq!(attachment["color"], "#e01e5a");
+        assert_eq!(
+            attachment["blocks"][0]["text"]["text"],
+            "*make stopped on host*\n`echo &lt;!channel&gt;` exited with code 1"
+        );
+        assert_eq!(
+            attachment["blocks"][1]["fields"][2]["text"],
+            "*Command*\necho &lt;!channel&gt;"
+        );
+    }
+
+    #[test]
+    fn discord_message() {
+        let message = discord(&StopEvent::example(ExitReason::Exited(0)));
+
+        assert_eq!(message["content"], "make stopped on host");
+        let embed = &message["embeds"][0];
+        assert_eq!(embed["description"], "make (pid 4242) exited with code 0");
+        assert_eq!(embed["color"], 0x2eb886);
+        assert_eq!(embed["timestamp"], "2023-11-14T22:14:50Z");
+        assert_eq!(embed["fields"][0]["name"], "Host");
+        assert_eq!(embed["fields"][0]["value"], "host");
+    }
+
+    #[test]
+    fn long_values_are_truncated() {
+        let mut event = StopEvent::example(ExitReason::Exited(0));
+        let arguments = std::iter::repeat_n("<&>".to_owned(), 2000);
+        event.command_line = Some(
+            std::iter::once("python".to_owned())
+                .chain(arguments)
+                .collect(),
+        );
+        event.description = "d".repeat(5000);
+
+        let slack = slack(&event);
+        let blocks = &slack["attachments"][0]["blocks"];
+        let text = blocks[0]["text"]["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_SLACK_TEXT_CHARS);
+        let command = blocks[1]["fields"][2]["text"].as_str().unwrap();
+        assert!(command.starts_with("*Command*\npython &lt;&amp;&gt; "));
+        assert!(command.ends_with('…'));
+        assert!(command.chars().count() <= MAX_SLACK_FIELD_CHARS);
+
+        let discord = discord(&event);
+        let embed = &discord["embeds"][0];
+        let description = embed["description"].as_str().unwrap();
+        assert_eq!(description.chars().count(), MAX_DISCORD_DESCRIPTION_CHARS);
+        let command = embed["fields"][2]["value"].as_str().unwrap();
+        assert_eq!(command.chars().count(), MAX_DISCORD_FIELD_CHARS);
+    }
+}
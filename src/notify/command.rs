@@ -0,0 +1,243 @@
+use std::io::{Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::{truncate, SendError, MAX_ERROR_BODY_CHARS};
+use crate::event::{ExitReason, StopEvent};
+
+/// The options for a command, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfig {
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+}
+
+impl CommandConfig {
+    /// A command that's run by the shell.
+    pub fn shell(command: &str) -> Self {
+        let shell = if cfg!(windows) {
+            ["cmd", "/C"]
+        } else {
+            ["sh", "-c"]
+        };
+        Self {
+            command: shell
+                .into_iter()
+                .map(str::to_owned)
+                .chain([command.to_owned()])
+                .collect(),
+        }
+    }
+
+    pub fn build(self) -> Result<Command, String> {
+        if self
+            .command
+            .first()
+            .is_none_or(|program| program.is_empty())
+        {
+            return Err("the command can't be empty".to_owned());
+        }
+        Ok(Command {
+            command: self.command,
+        })
+    }
+}
+
+/// A command that's run to deliver notifications.
+///
+/// It gets the event's details in environment variables (`NOTIF_PROCESS_NAME`, `NOTIF_PID`,
+/// `NOTIF_EXIT_CODE`, `NOTIF_DURATION_SECS` and others, which are empty when they aren't known)
+/// and as the JSON body on stdin. It fails if it exits with a non-zero code, with its stderr as the
+/// reason, and that isn't retried.
+pub struct Command {
+    command: Vec<String>,
+}
+
+impl Command {
+    /// Runs the command once, killing it if it takes longer than the timeout.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let deadline = Instant::now() + timeout;
+        let mut child = std::process::Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .envs(env_vars(event))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| SendError::permanent(format!("failed to run {}: {e}", self.command[0])))?;
+
+        // These happen on other threads so a command that doesn't read all of its stdin (or
+        // writes a lot to stderr) can't block it.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let json = event.to_json().to_string();
+        // The command doesn't have to read it.
+        std::thread::spawn(move || drop(stdin.write_all(json.as_bytes())));
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let (sender, chunks) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(n @ 1..) = stderr.read(&mut buf) {
+                if sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let status = wait_with_timeout(&mut child, timeout)?;
+        if status.success() {
+            return Ok(());
+        }
+        // Anything the command started in the background could keep stderr open, so this only
+        // waits for it until the deadline.
+        let stderr = read_until(&chunks, deadline);
+
+        let mut error = format!("command {}", ExitReason::from_status(status));
+        let stderr = String::from_utf8_lossy(&stderr);
+        let stderr = stderr.trim();
+        if !stderr.is_empty() {
+            error.push_str(": ");
+            error.push_str(&truncate(stderr, MAX_ERROR_BODY_CHARS));
+        }
+        // Running it again would repeat whatever it did before failing.
+        Err(SendError::permanent(error))
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        let vars: Vec<String> = env_vars(event)
+            .iter()
+            .map(|(name, value)| format!("{name}={value:?}"))
+            .collect();
+        format!(
+            "run {:?}\nwith {}\nand stdin {}",
+            self.command,
+            vars.join(" "),
+            event.to_json()
+        )
+    }
+
+    pub fn destination(&self) -> String {
+        self.command.join("\0")
+    }
+}
+
+/// The environment variables with the event's details.
+fn env_vars(event: &StopEvent) -> Vec<(&'static str, String)> {
+    let fields = event.fields();
+    let value = |key: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| *name == key)
+            .and_then(|(_, value)| value.clone())
+            .unwrap_or_default()
+    };
+
+    vec![
+        ("NOTIF_DESCRIPTION", event.description.clone()),
+        ("NOTIF_PROCESS_NAME", event.process_name.clone()),
+        ("NOTIF_PID", value("pid")),
+        ("NOTIF_HOSTNAME", event.hostname.clone()),
+        ("NOTIF_EXIT_CODE", value("exit_code")),
+        ("NOTIF_SIGNAL", value("signal")),
+        ("NOTIF_DURATION_SECS", value("runtime_secs")),
+    ]
+}
+
+/// Collects the chunks that are sent before the deadline (or until the sender is dropped).
+fn read_until(chunks: &mpsc::Receiver<Vec<u8>>, deadline: Instant) -> Vec<u8> {
+    let mut output = Vec::new();
+    while let Ok(chunk) = chunks.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        output.extend_from_slice(&chunk);
+    }
+    output
+}
+
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<std::process::ExitStatus, String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                drop(child.kill());
+                drop(child.wait());
+                return Err(format!(
+                    "command timed out after {}",
+                    humantime::format_duration(timeout)
+                ));
+            }
+            Err(e) => return Err(format!("failed to wait for the command: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_variables() {
+        let event = StopEvent::example(ExitReason::Signaled {
+            signal: 9,
+            core_dumped: false,
+        });
+        let vars = env_vars(&event);
+        let var = |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .unwrap()
+                .1
+                .as_str()
+        };
+
+        assert_eq!(var("NOTIF_PROCESS_NAME"), "make");
+        assert_eq!(var("NOTIF_PID"), "4242");
+        assert_eq!(var("NOTIF_HOSTNAME"), "host");
+        assert_eq!(var("NOTIF_EXIT_CODE"), "");
+        assert_eq!(var("NOTIF_SIGNAL"), "9");
+        assert_eq!(var("NOTIF_DURATION_SECS"), "90");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fails_with_stderr() {
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let command = CommandConfig::shell("echo \"$NOTIF_PID\" >&2; exit 3")
+            .build()
+            .unwrap();
+        let result = command.send(&event, Duration::from_secs(5));
+        assert_eq!(
+            result,
+            Err(SendError::permanent(
+                "command exited with code 3: 4242".to_owned()
+            ))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn background_processes_do_not_hold_it_up() {
+        let event = StopEvent::example(ExitReason::Exited(0));
+        // The background `sleep` keeps stderr open after the shell exits.
+        for (script, expected) in [
+            ("sleep 5 & exit 0", Ok(())),
+            (
+                "sleep 5 & echo oops >&2; exit 1",
+                Err(SendError::permanent(
+                    "command exited with code 1: oops".to_owned(),
+                )),
+            ),
+        ] {
+            let command = CommandConfig::shell(script).build().unwrap();
+            let start = Instant::now();
+            assert_eq!(command.send(&event, Duration::from_secs(1)), expected);
+            assert!(start.elapsed() < Duration::from_secs(2), "{script}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::escape_html;
+use crate::event::StopEvent;
+
+/// The options for desktop notifications, as given in the config file (or on the command line).
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesktopConfig {
+    /// An icon name (from the icon theme) or path, instead of the usual info/error icons.
+    pub icon: Option<String>,
+}
+
+impl DesktopConfig {
+    pub fn build(self) -> Result<Desktop, String> {
+        if !cfg!(target_os = "linux") {
+            return Err("desktop notifications are only supported on Linux".to_owned());
+        }
+        Ok(Desktop { icon: self.icon })
+    }
+}
+
+/// How urgent a notification is, which notification servers show differently (critical ones
+/// usually stay until they're dismissed).
+#[derive(Clone, Copy)]
+enum Urgency {
+    Normal = 1,
+    Critical = 2,
+}
+
+/// Notifications on the desktop, through the notification server on the session bus.
+pub struct Desktop {
+    icon: Option<String>,
+}
+
+impl Desktop {
+    /// Makes a single attempt at showing the notification.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), String> {
+        let (urgency, icon) = self.urgency_and_icon(event);
+        dbus::notify(&event.title(), &body(event), icon, urgency, timeout)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        let (urgency, icon) = self.urgency_and_icon(event);
+        let urgency = match urgency {
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        };
+        format!(
+            "{} ({urgency} urgency, icon {icon})\n{}",
+            event.title(),
+            body(event)
+        )
+    }
+
+    pub fn destination(&self) -> String {
+        String::new()
+    }
+
+    fn urgency_and_icon<'a>(&'a self, event: &StopEvent) -> (Urgency, &'a str) {
+        let (urgency, icon) = match event.succeeded() {
+            Some(false) => (Urgency::Critical, "dialog-error"),
+            _ => (Urgency::Normal, "dialog-information"),
+        };
+        (urgency, self.icon.as_deref().unwrap_or(icon))
+    }
+}
+
+/// The report, escaped since notification servers may support (a little) markup.
+fn body(event: &StopEvent) -> String {
+    escape_html(&event.report())
+}
+
+#[cfg(target_os = "linux")]
+mod dbus {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use zbus::zvariant::Value;
+
+    use super::Urgency;
+
+    /// Calls `org.freedesktop.Notifications.Notify` on the session bus.
+    pub fn notify(
+        summary: &str,
+        body: &str,
+        icon: &str,
+        urgency: Urgency,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let connection = zbus::blocking::connection::Builder::session()
+            .and_then(|builder| builder.method_timeout(timeout).build())
+            .map_err(|e| format!("failed to connect to the session bus: {e}"))?;
+
+        let actions: &[&str] = &[];
+        let hints = HashMap::from([("urgency", Value::U8(urgency as u8))]);
+        // -1 leaves how long it's shown up to the notification server.
+        let expire_timeout = -1i32;
+        connection
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "Notify",
+                &(
+                    "notif_stopped",
+                    0u32,
+                    icon,
+                    summary,
+                    body,
+                    actions,
+                    hints,
+                    expire_timeout,
+                ),
+            )
+            .map(drop)
+            .map_err(|e| format!("failed to show the notification: {e}"))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod dbus {
+    use std::time::Duration;
+
+    use super::Urgency;
+
+    pub fn notify(
+        _summary: &str,
+        _body: &str,
+        _icon: &str,
+        _urgency: Urgency,
+        _timeout: Duration,
+    ) -> Result<(), String> {
+        Err("desktop notifications are only supported on Linux".to_owned())
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+
+use super::read_secret;
+use crate::event::StopEvent;
+
+/// How the connection to the SMTP server is secured.
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Upgrade the connection with STARTTLS (port 587 by default)
+    Starttls,
+    /// Use TLS from the start (port 465 by default)
+    Tls,
+    /// Don't encrypt anything (port 25 by default), only for trusted local relays
+    None,
+}
+
+impl Security {
+    fn default_port(self) -> u16 {
+        match self {
+            Self::Starttls => 587,
+            Self::Tls => 465,
+            Self::None => 25,
+        }
+    }
+}
+
+/// The options for sending email, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    /// The SMTP server's host name.
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default = "default_security")]
+    pub security: Security,
+    /// The user name to log in with, if the server needs it.
+    pub username: Option<String>,
+    pub password_file: Option<PathBuf>,
+    /// The environment variable to read the password from (if there's no file).
+    pub password_env: Option<String>,
+    /// The sender, `notif_stopped@<host name>` by default.
+    pub from: Option<String>,
+    pub to: Vec<String>,
+}
+
+fn default_security() -> Security {
+    Security::Starttls
+}
+
+impl EmailConfig {
+    /// Validates the options and loads the password.
+    pub fn build(self) -> Result<Email, String> {
+        if self.host.is_empty() {
+            return Err("an smtp server is needed".to_owned());
+        }
+        if self.to.is_empty() {
+            return Err("at least one recipient is needed".to_owned());
+        }
+
+        let from = match self.from {
+            Some(from) => from,
+            None => format!(
+                "notif_stopped@{}",
+                gethostname::gethostname().to_string_lossy()
+            ),
+        };
+        let from = parse_mailbox(&from)?;
+        let to = self
+            .to
+            .iter()
+            .map(|to| parse_mailbox(to))
+            .collect::<Result<_, _>>()?;
+
+        let credentials = match self.username {
+            Some(username) => {
+                let password =
+                    read_secret(self.password_file.as_deref(), self.password_env.as_deref())?
+                        .ok_or_else(|| match &self.password_env {
+                            Some(var) => format!(
+                                "smtp auth needs a password (set `{var}` or use a password file)"
+                            ),
+                            None => "smtp auth needs a password file".to_owned(),
+                        })?;
+                Some(Credentials::new(username, password))
+            }
+            None => None,
+        };
+
+        Ok(Email {
+            port: self.port.unwrap_or(self.security.default_port()),
+            host: self.host,
+            security: self.security,
+            credentials,
+            from,
+            to,
+        })
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, String> {
+    address
+        .parse()
+        .map_err(|e| format!("invalid email address {address:?}: {e}"))
+}
+
+/// Recipients to email notifications to, through an SMTP server.
+pub struct Email {
+    host: String,
+    port: u16,
+    security: Security,
+    credentials: Option<Credentials>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Email {
+    /// Makes a single attempt at sending the email.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), String> {
+        let message = self.message(event)?;
+
+        let tls = match self.security {
+            Security::None => Tls::None,
+            security => {
+                let parameters = TlsParameters::new(self.host.clone())
+                    .map_err(|e| format!("failed to set up tls: {e}"))?;
+                match security {
+                    Security::Tls => Tls::Wrapper(parameters),
+                    _ => Tls::Required(parameters),
+                }
+            }
+        };
+        let mut transport = SmtpTransport::builder_dangerous(self.host.as_str())
+            .port(self.port)
+            .tls(tls)
+            .timeout(Some(timeout));
+        if let Some(credentials) = &self.credentials {
+            transport = transport.credentials(credentials.clone());
+        }
+
+        transport
+            .build()
+            .send(&message)
+            .map(drop)
+            .map_err(|e| format!("sending email failed: {e}"))
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        match self.message(event) {
+            Ok(message) => String::from_utf8_lossy(&message.formatted()).into_owned(),
+            Err(e) => e,
+        }
+    }
+
+    pub fn destination(&self) -> String {
+        format!(
+            "{}:{} {}",
+            self.host,
+            self.port,
+            self.to
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+
+    fn message(&self, event: &StopEvent) -> Result<Message, String> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(event.title())
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        message
+            .body(event.report())
+            .map_err(|e| format!("failed to build email: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::event::ExitReason;
+
+    fn config(to: &[&str]) -> EmailConfig {
+        EmailConfig {
+            host: "127.0.0.1".to_owned(),
+            port: None,
+            security: Security::None,
+            username: None,
+            password_file: None,
+            password_env: None,
+            from: Some("Builds <builds@example.com>".to_owned()),
+            to: to.iter().map(|to| to.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn message() {
+        let email = config(&["a@example.com", "B <b@example.com>"])
+            .build()
+            .unwrap();
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let message = String::from_utf8(email.message(&event).unwrap().formatted()).unwrap();
+
+        assert!(message.contains("From: Builds <builds@example.com>\r\n"));
+        assert!(message.contains("To: a@example.com, B <b@example.com>\r\n"));
+        assert!(message.contains("Subject: make stopped on host\r\n"));
+        assert!(message.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(message.ends_with(&format!("\r\n\r\n{}", event.report().replace('\n', "\r\n"))));
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        assert!(config(&[]).build().is_err());
+        assert!(config(&["not an address"]).build().is_err());
+        let mut auth = config(&["a@example.com"]);
+        auth.username = Some("user".to_owned());
+        assert!(auth.build().is_err());
+    }
+
+    #[test]
+    fn sends_through_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Just enough of an SMTP server to accept a message, returning the commands and data it
+        // was sent.
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = Vec::new();
+            writer.write_all(b"220 localhost\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_owned();
+                let reply: &[u8] = match line.as_str() {
+                    "." if in_data => {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    }
+                    _ if in_data => {
+                        transcript.push(line);
+                        continue;
+                    }
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => b"221 bye\r\n",
+                    _ if line.starts_with("EHLO") => b"250 localhost\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                transcript.push(line.clone());
+                writer.write_all(reply).unwrap();
+                if line == "QUIT" {
+                    break;
+                }
+            }
+            transcript
+        });
+
+        let mut config = config(&["a@example.com"]);
+        config.port = Some(port);
+        let event = StopEvent::example(ExitReason::Exited(1));
+        config
+            .build()
+            .unwrap()
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let transcript = server.join().unwrap();
+        assert!(transcript.contains(&"MAIL FROM:<builds@example.com>".to_owned()));
+        assert!(transcript.contains(&"RCPT TO:<a@example.com>".to_owned()));
+        assert!(transcript.contains(&"Subject: make stopped on host".to_owned()));
+        assert!(transcript.contains(&"make (pid 4242) exited with code 1".to_owned()));
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{read_secret, send_request, SendError};
+use crate::event::StopEvent;
+
+/// The options for a Gotify server, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GotifyConfig {
+    /// The server's base url, e.g. `https://gotify.example.com`.
+    pub url: String,
+    pub token_file: Option<PathBuf>,
+    /// The environment variable to read the app token from (if there's no file).
+    pub token_env: Option<String>,
+    /// The priority for processes that didn't fail (5 by default).
+    pub priority: Option<u8>,
+    /// The priority for processes that failed (8 by default).
+    pub failure_priority: Option<u8>,
+}
+
+impl GotifyConfig {
+    /// Validates the options and loads the app token.
+    pub fn build(self) -> Result<Gotify, String> {
+        if !self.url.starts_with("http") {
+            return Err("gotify urls must be http(s) urls".to_owned());
+        }
+        let token = read_secret(self.token_file.as_deref(), self.token_env.as_deref())?
+            .ok_or_else(|| match &self.token_env {
+                Some(var) => format!("an app token is needed (set `{var}` or use a token file)"),
+                None => "an app token file is needed".to_owned(),
+            })?;
+
+        Ok(Gotify {
+            url: format!("{}/message", self.url.trim_end_matches('/')),
+            token,
+            priority: self.priority.unwrap_or(5),
+            failure_priority: self.failure_priority.unwrap_or(8),
+        })
+    }
+}
+
+/// A Gotify server to send notifications to.
+pub struct Gotify {
+    /// The url of the message endpoint.
+    url: String,
+    token: String,
+    priority: u8,
+    failure_priority: u8,
+}
+
+impl Gotify {
+    /// Makes a single attempt at sending the notification.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let request = minreq::post(self.url.as_str())
+            .with_header("X-Gotify-Key", self.token.as_str())
+            .with_header("Content-Type", "application/json")
+            .with_body(self.body(event).to_string());
+        send_request(request, timeout, &[]).map(drop)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        format!("POST {}\n{}", self.url, self.body(event))
+    }
+
+    pub fn destination(&self) -> String {
+        self.url.clone()
+    }
+
+    fn body(&self, event: &StopEvent) -> serde_json::Value {
+        let priority = match event.succeeded() {
+            Some(false) => self.failure_priority,
+            _ => self.priority,
+        };
+        json!({
+            "title": event.title(),
+            "message": event.report(),
+            "priority": priority,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+    use crate::notify::serve_once;
+
+    fn config(url: &str) -> GotifyConfig {
+        std::env::set_var("NOTIF_TEST_GOTIFY_TOKEN", "app-token");
+        GotifyConfig {
+            url: url.to_owned(),
+            token_file: None,
+            token_env: Some("NOTIF_TEST_GOTIFY_TOKEN".to_owned()),
+            priority: None,
+            failure_priority: None,
+        }
+    }
+
+    #[test]
+    fn priority_depends_on_the_outcome() {
+        let gotify = config("https://gotify.example.com").build().unwrap();
+        let priority = |reason| gotify.body(&StopEvent::example(reason))["priority"].clone();
+        assert_eq!(priority(ExitReason::Exited(0)), 5);
+        assert_eq!(priority(ExitReason::Exited(1)), 8);
+        assert_eq!(priority(ExitReason::Unknown), 5);
+
+        let mut config = config("https://gotify.example.com");
+        config.priority = Some(1);
+        config.failure_priority = Some(10);
+        let gotify = config.build().unwrap();
+        let priority = |reason| gotify.body(&StopEvent::example(reason))["priority"].clone();
+        assert_eq!(priority(ExitReason::Exited(0)), 1);
+        assert_eq!(priority(ExitReason::Exited(1)), 10);
+    }
+
+    #[test]
+    fn urls_are_joined_with_the_endpoint() {
+        for url in ["https://gotify.example.com", "https://gotify.example.com/"] {
+            let gotify = config(url).build().unwrap();
+            assert_eq!(gotify.destination(), "https://gotify.example.com/message");
+        }
+    }
+
+    #[test]
+    fn a_token_is_needed() {
+        let mut unset_var = config("https://gotify.example.com");
+        unset_var.token_env = Some("NOTIF_TEST_GOTIFY_TOKEN_UNSET".to_owned());
+        assert_eq!(
+            unset_var.build().err(),
+            Some(
+                "an app token is needed (set `NOTIF_TEST_GOTIFY_TOKEN_UNSET` or use a token file)"
+                    .to_owned()
+            )
+        );
+
+        let mut no_token = config("https://gotify.example.com");
+        no_token.token_env = None;
+        assert_eq!(
+            no_token.build().err(),
+            Some("an app token file is needed".to_owned())
+        );
+    }
+
+    #[test]
+    fn sends_the_message_with_the_token() {
+        let (url, server) = serve_once(200, "{}");
+        let event = StopEvent::example(ExitReason::Exited(1));
+        config(&format!("{url}/"))
+            .build()
+            .unwrap()
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /message HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nX-Gotify-Key: app-token\r\n"));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["title"], "make stopped on host");
+        assert_eq!(body["message"], event.report());
+        assert_eq!(body["priority"], 8);
+    }
+}
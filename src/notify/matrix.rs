@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{escape_html, percent_encode, read_secret, send_request, SendError};
+use crate::event::StopEvent;
+
+const HOMESERVER: &str = "https://matrix.org";
+
+/// The options for a Matrix room, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    /// The homeserver's base url, instead of `https://matrix.org`.
+    pub homeserver: Option<String>,
+    /// The room's id (like `!abc123:example.com`), not an alias.
+    pub room_id: Option<String>,
+    /// The environment variable to read the room id from (if it isn't given directly).
+    pub room_id_env: Option<String>,
+    pub access_token_file: Option<PathBuf>,
+    /// The environment variable to read the access token from (if there's no file).
+    pub access_token_env: Option<String>,
+}
+
+impl MatrixConfig {
+    /// Validates the options and loads the access token.
+    pub fn build(self) -> Result<Matrix, String> {
+        let access_token = read_secret(
+            self.access_token_file.as_deref(),
+            self.access_token_env.as_deref(),
+        )?
+        .ok_or_else(|| match &self.access_token_env {
+            Some(var) => format!("an access token is needed (set `{var}` or use a token file)"),
+            None => "an access token file is needed".to_owned(),
+        })?;
+        let room_id = match (self.room_id, &self.room_id_env) {
+            (Some(room_id), _) => room_id,
+            (None, Some(var)) => std::env::var(var)
+                .ok()
+                .filter(|room_id| !room_id.is_empty())
+                .ok_or_else(|| format!("a room id is needed (set `{var}`)"))?,
+            (None, None) => return Err("a room id is needed".to_owned()),
+        };
+        if !room_id.starts_with('!') {
+            return Err("matrix room ids start with `!` (aliases can't be used)".to_owned());
+        }
+        let homeserver = self.homeserver.unwrap_or_else(|| HOMESERVER.to_owned());
+        if !homeserver.starts_with("http") {
+            return Err("matrix homeservers must be http(s) urls".to_owned());
+        }
+
+        Ok(Matrix {
+            url: format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message",
+                homeserver.trim_end_matches('/'),
+                percent_encode(&room_id)
+            ),
+            access_token,
+        })
+    }
+}
+
+/// A Matrix room to send notifications to.
+pub struct Matrix {
+    /// The url to send messages to, without the transaction id.
+    url: String,
+    access_token: String,
+}
+
+impl Matrix {
+    /// Makes a single attempt at sending the message.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let url = format!("{}/{}", self.url, transaction_id(event));
+        let request = minreq::put(url)
+            .with_header("Authorization", format!("Bearer {}", self.access_token))
+            .with_header("Content-Type", "application/json")
+            .with_body(body(event).to_string());
+        send_request(request, timeout, &[]).map(drop)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        format!(
+            "PUT {}/{}\n{}",
+            self.url,
+            transaction_id(event),
+            body(event)
+        )
+    }
+
+    pub fn destination(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// The id that makes the homeserver ignore repeats of a message, which is the same for every
+/// attempt at sending an event (including from the outbox).
+fn transaction_id(event: &StopEvent) -> String {
+    let millis = event
+        .stopped_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("notif_stopped-{millis}-{}", event.pid.unwrap_or_default())
+}
+
+/// An `m.room.message` with the report as plain text and as HTML.
+fn body(event: &StopEvent) -> serde_json::Value {
+    let mut html = format!(
+        "<b>{}</b><br>{}<br>",
+        escape_html(&event.title()),
+        escape_html(&event.description)
+    );
+    for (label, value) in event.details() {
+        html.push_str(&format!("<br><b>{label}:</b> {}", escape_html(&value)));
+    }
+
+    json!({
+        "msgtype": "m.text",
+        "body": format!("{}\n{}", event.title(), event.report()),
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+    use crate::notify::serve_once;
+
+    fn matrix(homeserver: Option<String>) -> Matrix {
+        std::env::set_var("NOTIF_TEST_MATRIX_TOKEN", "syt_token");
+        MatrixConfig {
+            homeserver,
+            room_id: Some("!room:example.com".to_owned()),
+            room_id_env: None,
+            access_token_file: None,
+            access_token_env: Some("NOTIF_TEST_MATRIX_TOKEN".to_owned()),
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn transaction_ids_are_stable() {
+        let event = StopEvent::example(ExitReason::Exited(0));
+        assert_eq!(transaction_id(&event), "notif_stopped-1700000090000-4242");
+        assert_eq!(transaction_id(&event), transaction_id(&event));
+
+        let mut other = StopEvent::example(ExitReason::Exited(0));
+        other.pid = Some(4243);
+        assert_ne!(transaction_id(&event), transaction_id(&other));
+    }
+
+    #[test]
+    fn message_body() {
+        let mut event = StopEvent::example(ExitReason::Exited(0));
+        event.command_line = Some(vec!["echo".to_owned(), "<b>&</b>".to_owned()]);
+        let body = body(&event);
+
+        assert_eq!(body["msgtype"], "m.text");
+        assert_eq!(body["format"], "org.matrix.custom.html");
+        let text = body["body"].as_str().unwrap();
+        assert!(text.starts_with("make stopped on host\nmake (pid 4242) exited with code 0\n"));
+        assert!(text.contains("\nCommand: echo <b>&</b>\n"));
+        let html = body["formatted_body"].as_str().unwrap();
+        assert!(html.starts_with("<b>make stopped on host</b><br>"));
+        assert!(html.contains("<br><b>Command:</b> echo &lt;b&gt;&amp;&lt;/b&gt;<br>"));
+    }
+
+    #[test]
+    fn room_ids_are_checked() {
+        std::env::set_var("NOTIF_TEST_MATRIX_TOKEN", "syt_token");
+        let config = MatrixConfig {
+            homeserver: None,
+            room_id: Some("#alias:example.com".to_owned()),
+            room_id_env: None,
+            access_token_file: None,
+            access_token_env: Some("NOTIF_TEST_MATRIX_TOKEN".to_owned()),
+        };
+        assert!(config.build().is_err());
+    }
+
+    #[test]
+    fn sends_to_the_homeserver() {
+        let (url, server) = serve_once(200, "{\"event_id\":\"$1\"}");
+        let event = StopEvent::example(ExitReason::Exited(0));
+        matrix(Some(url))
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with(
+            "PUT /_matrix/client/v3/rooms/%21room%3Aexample.com/send/m.room.message/\
+             notif_stopped-1700000090000-4242 HTTP/1.1\r\n"
+        ));
+        assert!(request.contains("\r\nAuthorization: Bearer syt_token\r\n"));
+        let (_, sent) = request.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(sent).unwrap(),
+            body(&event)
+        );
+    }
+}
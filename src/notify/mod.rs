@@ -0,0 +1,536 @@
+pub mod chat;
+pub mod command;
+pub mod desktop;
+pub mod email;
+pub mod gotify;
+pub mod matrix;
+pub mod mqtt;
+pub mod ntfy;
+pub mod pushover;
+pub mod syslog;
+pub mod telegram;
+pub mod webhook;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::event::StopEvent;
+use command::Command;
+use desktop::Desktop;
+use email::Email;
+use gotify::Gotify;
+use matrix::Matrix;
+use mqtt::Mqtt;
+use ntfy::Ntfy;
+use pushover::Pushover;
+use syslog::{Journald, Syslog};
+use telegram::Telegram;
+use webhook::Webhook;
+
+/// Somewhere to send notifications to.
+pub struct Target {
+    /// The name used to refer to the target in output (and in the outbox).
+    pub name: String,
+    /// Whether failing to notify this target makes the run fail.
+    pub required: bool,
+    pub notifier: Notifier,
+}
+
+impl Target {
+    /// Identifies the target across runs, for the outbox. Unlike names (which for webhooks depend
+    /// on how many there are), this only depends on the kind of target and where it sends
+    /// notifications. That's hashed, since urls can have secrets in them.
+    pub fn key(&self) -> String {
+        let (kind, destination) = self.notifier.destination();
+        format!("{kind}-{:016x}", fnv1a(destination.as_bytes()))
+    }
+}
+
+/// How a target gets notified.
+pub enum Notifier {
+    Webhook(Webhook),
+    Ntfy(Ntfy),
+    Gotify(Gotify),
+    Pushover(Pushover),
+    Telegram(Telegram),
+    Matrix(Matrix),
+    Email(Email),
+    Desktop(Desktop),
+    Command(Command),
+    Mqtt(Mqtt),
+    Journald(Journald),
+    Syslog(Syslog),
+}
+
+impl Notifier {
+    /// Makes a single attempt at sending the notification.
+    fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        match self {
+            Self::Webhook(webhook) => webhook.send(event, timeout),
+            Self::Ntfy(ntfy) => ntfy.send(event, timeout),
+            Self::Gotify(gotify) => gotify.send(event, timeout),
+            Self::Pushover(pushover) => pushover.send(event, timeout),
+            Self::Telegram(telegram) => telegram.send(event, timeout),
+            Self::Matrix(matrix) => matrix.send(event, timeout),
+            Self::Email(email) => email.send(event, timeout).map_err(SendError::from),
+            Self::Desktop(desktop) => desktop.send(event, timeout).map_err(SendError::from),
+            Self::Command(command) => command.send(event, timeout),
+            Self::Mqtt(mqtt) => mqtt.send(event, timeout).map_err(SendError::from),
+            Self::Journald(journald) => journald.send(event, timeout).map_err(SendError::from),
+            Self::Syslog(syslog) => syslog.send(event, timeout).map_err(SendError::from),
+        }
+    }
+
+    /// A description of what would be sent, for dry runs. This never includes secrets.
+    pub fn preview(&self, event: &StopEvent) -> String {
+        match self {
+            Self::Webhook(webhook) => webhook.preview(event),
+            Self::Ntfy(ntfy) => ntfy.preview(event),
+            Self::Gotify(gotify) => gotify.preview(event),
+            Self::Pushover(pushover) => pushover.preview(event),
+            Self::Telegram(telegram) => telegram.preview(event),
+            Self::Matrix(matrix) => matrix.preview(event),
+            Self::Email(email) => email.preview(event),
+            Self::Desktop(desktop) => desktop.preview(event),
+            Self::Command(command) => command.preview(event),
+            Self::Mqtt(mqtt) => mqtt.preview(event),
+            Self::Journald(journald) => journald.preview(event),
+            Self::Syslog(syslog) => syslog.preview(event),
+        }
+    }
+
+    /// The kind of notifier and where it sends notifications (like its url).
+    fn destination(&self) -> (&'static str, String) {
+        match self {
+            Self::Webhook(webhook) => ("webhook", webhook.destination()),
+            Self::Ntfy(ntfy) => ("ntfy", ntfy.destination()),
+            Self::Gotify(gotify) => ("gotify", gotify.destination()),
+            Self::Pushover(pushover) => ("pushover", pushover.destination()),
+            Self::Telegram(telegram) => ("telegram", telegram.destination()),
+            Self::Matrix(matrix) => ("matrix", matrix.destination()),
+            Self::Email(email) => ("email", email.destination()),
+            Self::Desktop(desktop) => ("desktop", desktop.destination()),
+            Self::Command(command) => ("command", command.destination()),
+            Self::Mqtt(mqtt) => ("mqtt", mqtt.destination()),
+            Self::Journald(journald) => ("journald", journald.destination()),
+            Self::Syslog(syslog) => ("syslog", syslog.destination()),
+        }
+    }
+}
+
+/// Why an attempt at sending a notification failed.
+#[derive(Debug, PartialEq)]
+pub struct SendError {
+    pub message: String,
+    /// Whether trying again could help. It can't for things like a 404 or a revoked token, which
+    /// will fail the same way, or a command that ran and failed (whose side effects would be
+    /// repeated).
+    pub retryable: bool,
+}
+
+impl SendError {
+    fn permanent(message: String) -> Self {
+        Self {
+            message,
+            retryable: false,
+        }
+    }
+}
+
+impl From<String> for SendError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            retryable: true,
+        }
+    }
+}
+
+/// How hard to try to deliver a notification.
+#[derive(Clone, Copy)]
+pub struct Retry {
+    /// How many times to retry after the first attempt fails.
+    pub retries: u32,
+    /// The delay before the first retry, which doubles with each retry after it.
+    pub initial_delay: Duration,
+    /// How long a single attempt can take.
+    pub timeout: Duration,
+    /// How long all of the attempts (and the delays between them) can take.
+    pub deadline: Duration,
+}
+
+impl Retry {
+    /// The same, but without any retries.
+    pub fn once(self) -> Self {
+        Self { retries: 0, ..self }
+    }
+
+    /// The delay before the retry following attempt number `attempt` (starting at 0). This is the
+    /// exponential backoff with up to half of it randomly taken off, so that many clients failing
+    /// at once don't all retry at the same moment.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt));
+        delay.mul_f64(1.0 - fastrand::f64() / 2.0)
+    }
+
+    /// Sends a notification to the target, retrying on failure.
+    fn send(&self, target: &Target, event: &StopEvent) -> Result<(), String> {
+        self.send_with(
+            &target.name,
+            |timeout| target.notifier.send(event, timeout),
+            std::thread::sleep,
+            Instant::now,
+        )
+    }
+
+    /// Makes attempts with `send` until one succeeds, fails in a way that can't be retried, or
+    /// there are no retries (or time) left. This takes how to sleep and tell the time so that it
+    /// can be tested without waiting.
+    fn send_with(
+        &self,
+        name: &str,
+        mut send: impl FnMut(Duration) -> Result<(), SendError>,
+        mut sleep: impl FnMut(Duration),
+        now: impl Fn() -> Instant,
+    ) -> Result<(), String> {
+        let deadline = now() + self.deadline;
+        let mut attempt = 0;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(now());
+            let error = match send(self.timeout.min(remaining)) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            let delay = self.delay(attempt);
+            attempt += 1;
+            if !error.retryable || attempt > self.retries || now() + delay >= deadline {
+                if attempt == 1 {
+                    return Err(error.message);
+                }
+                return Err(format!(
+                    "{} (gave up after {attempt} attempts)",
+                    error.message
+                ));
+            }
+
+            eprintln!(
+                "\x1b[93mwarning\x1b[0m: {name}: {}, retrying in {}",
+                error.message,
+                humantime::format_duration(Duration::from_millis(delay.as_millis() as u64))
+            );
+            sleep(delay);
+        }
+    }
+}
+
+/// The outcome of notifying a target.
+pub struct Delivery<'a> {
+    pub target: &'a Target,
+    pub result: Result<(), String>,
+}
+
+/// Notifies all of the targets in parallel, returning the outcome for each (in the same order).
+pub fn notify_all<'a>(
+    targets: &[&'a Target],
+    event: &StopEvent,
+    retry: Retry,
+) -> Vec<Delivery<'a>> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = targets
+            .iter()
+            .map(|&target| scope.spawn(move || retry.send(target, event)))
+            .collect();
+
+        targets
+            .iter()
+            .zip(handles)
+            .map(|(&target, handle)| Delivery {
+                target,
+                result: handle
+                    .join()
+                    .unwrap_or_else(|_| Err("sending the notification panicked".to_owned())),
+            })
+            .collect()
+    })
+}
+
+/// Sends an HTTP request, failing if the response's status isn't one of the expected ones (or, if
+/// there aren't any, isn't a 2xx status). The error includes the start of the response's body,
+/// since that's usually where the reason is. Only failed requests and responses with a 408, 429
+/// or 5xx status are worth retrying.
+fn send_request(
+    request: minreq::Request,
+    timeout: Duration,
+    expected_statuses: &[i32],
+) -> Result<minreq::Response, SendError> {
+    // Timeouts are in whole seconds, and `0` would mean no timeout at all.
+    let response = request
+        .with_timeout(timeout.as_secs().max(1))
+        .send()
+        .map_err(|e| format!("http request failed: {e}"))?;
+    let code = response.status_code;
+    let expected = match expected_statuses {
+        [] => (200..300).contains(&code),
+        expected => expected.contains(&code),
+    };
+    if expected {
+        return Ok(response);
+    }
+
+    let mut error = format!(
+        "server responded with status {code} {}",
+        response.reason_phrase
+    );
+    let body = String::from_utf8_lossy(response.as_bytes());
+    let body = body.trim();
+    if !body.is_empty() {
+        error.push_str(": ");
+        error.push_str(&truncate(body, MAX_ERROR_BODY_CHARS));
+    }
+    Err(SendError {
+        message: error,
+        retryable: matches!(code, 408 | 429 | 500..=599),
+    })
+}
+
+/// How much of a response's body (or a command's stderr) to include in errors.
+const MAX_ERROR_BODY_CHARS: usize = 200;
+
+/// Truncates text to a number of chars, ending it with an ellipsis (which counts towards the
+/// limit) if anything was cut off.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().nth(max_chars).is_none() {
+        return text.to_owned();
+    }
+    let end = text
+        .char_indices()
+        .nth(max_chars.saturating_sub(1))
+        .map_or(text.len(), |(end, _)| end);
+    format!("{}…", &text[..end])
+}
+
+/// Escapes the characters that are special in HTML text (which Slack's text and some
+/// notification servers' markup also use).
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Percent-encodes everything but unreserved characters, for form fields and url paths.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// The 64-bit FNV-1a hash, which (unlike std's hashers) is the same in every build.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Reads a secret (like a token) from a file, or else from an environment variable. Empty
+/// variables count as unset.
+fn read_secret(file: Option<&Path>, var: Option<&str>) -> Result<Option<String>, String> {
+    match (file, var) {
+        (Some(path), _) => read_secret_file(path).map(Some),
+        (None, Some(var)) => Ok(std::env::var(var).ok().filter(|secret| !secret.is_empty())),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Reads a secret from a file, ignoring surrounding whitespace.
+fn read_secret_file(path: &Path) -> Result<String, String> {
+    let secret = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    match secret.trim() {
+        "" => Err(format!("{} is empty", path.display())),
+        secret => Ok(secret.to_owned()),
+    }
+}
+
+/// Serves a single HTTP request, responding with the status and body. This returns the server's
+/// url and a handle that gives the raw request once it's been served.
+#[cfg(test)]
+fn serve_once(status: u16, body: &'static str) -> (String, std::thread::JoinHandle<String>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut content = vec![0; length];
+        reader.read_exact(&mut content).unwrap();
+        request.push_str(&String::from_utf8(content).unwrap());
+
+        let response = format!(
+            "HTTP/1.1 {status} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        request
+    });
+    (url, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncating_keeps_to_the_limit() {
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("abcd", 3), "ab…");
+        assert_eq!(truncate("äöüß", 3), "äö…");
+        assert_eq!(truncate(&"a".repeat(300), 250).chars().count(), 250);
+    }
+
+    #[test]
+    fn html_escaping() {
+        assert_eq!(
+            escape_html("a < b && c > d"),
+            "a &lt; b &amp;&amp; c &gt; d"
+        );
+        assert_eq!(escape_html("&lt;"), "&amp;lt;");
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("a-Z_0.~"), "a-Z_0.~");
+        assert_eq!(percent_encode("!a b/ä"), "%21a%20b%2F%C3%A4");
+    }
+
+    fn retry(retries: u32, deadline: Duration) -> Retry {
+        Retry {
+            retries,
+            initial_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            deadline,
+        }
+    }
+
+    /// Runs `send_with` on a clock that only moves when it sleeps, returning its result and how
+    /// long it slept for each time.
+    fn send_with(
+        retry: Retry,
+        mut send: impl FnMut() -> Result<(), SendError>,
+    ) -> (Result<(), String>, Vec<Duration>) {
+        let start = Instant::now();
+        let slept = std::cell::RefCell::new(Vec::new());
+        let result = retry.send_with(
+            "test",
+            |_| send(),
+            |delay| slept.borrow_mut().push(delay),
+            || start + slept.borrow().iter().sum::<Duration>(),
+        );
+        (result, slept.into_inner())
+    }
+
+    #[test]
+    fn delays_back_off_with_jitter() {
+        let retry = retry(5, Duration::from_secs(300));
+        for attempt in 0..5 {
+            let backoff = Duration::from_secs(1 << attempt);
+            for _ in 0..100 {
+                let delay = retry.delay(attempt);
+                assert!(delay > backoff / 2 && delay <= backoff, "{delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn retries_until_they_run_out() {
+        let mut attempts = 0;
+        let (result, slept) = send_with(retry(3, Duration::from_secs(300)), || {
+            attempts += 1;
+            Err("failed".to_owned().into())
+        });
+        assert_eq!(result, Err("failed (gave up after 4 attempts)".to_owned()));
+        assert_eq!(attempts, 4);
+        assert_eq!(slept.len(), 3);
+
+        let mut attempts = 0;
+        let (result, _) = send_with(retry(3, Duration::from_secs(300)), || {
+            attempts += 1;
+            match attempts {
+                1 => Err("failed".to_owned().into()),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn retries_stop_before_the_deadline() {
+        // The first two delays add up to at most 3s, and the third would take it past 3.5s.
+        let (result, slept) = send_with(retry(10, Duration::from_millis(3250)), || {
+            Err("failed".to_owned().into())
+        });
+        assert_eq!(result, Err("failed (gave up after 3 attempts)".to_owned()));
+        assert_eq!(slept.len(), 2);
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let (result, slept) = send_with(retry(5, Duration::from_secs(300)), || {
+            Err(SendError::permanent("not found".to_owned()))
+        });
+        assert_eq!(result, Err("not found".to_owned()));
+        assert!(slept.is_empty());
+    }
+
+    #[test]
+    fn only_some_statuses_are_retried() {
+        for (status, retryable) in [
+            (400, false),
+            (401, false),
+            (404, false),
+            (408, true),
+            (429, true),
+            (500, true),
+            (503, true),
+        ] {
+            let (url, server) = serve_once(status, "");
+            let result = send_request(minreq::get(url), Duration::from_secs(5), &[]);
+            server.join().unwrap();
+            assert_eq!(result.unwrap_err().retryable, retryable, "{status}");
+        }
+
+        // Nothing's listening on the port once the listener is dropped.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let result = send_request(minreq::get(url), Duration::from_secs(5), &[]);
+        assert!(result.unwrap_err().retryable);
+    }
+}
@@ -0,0 +1,551 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::read_secret;
+use crate::event::StopEvent;
+
+/// The options for publishing to an MQTT broker, as given in the config file (or on the command
+/// line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// The broker's url, `mqtt://host[:port]` (1883 by default) or `mqtts://host[:port]` for TLS
+    /// (8883 by default).
+    pub url: String,
+    pub topic: String,
+    /// 0 (at most once), 1 (at least once, the default) or 2 (exactly once).
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    /// Whether the broker should keep the message for clients that subscribe later.
+    #[serde(default)]
+    pub retain: bool,
+    pub username: Option<String>,
+    pub password_file: Option<PathBuf>,
+    /// The environment variable to read the password from (if there's no file).
+    pub password_env: Option<String>,
+    /// `notif_stopped-<pid>` by default.
+    pub client_id: Option<String>,
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+impl MqttConfig {
+    /// Validates the options and loads the password.
+    pub fn build(self) -> Result<Mqtt, String> {
+        let (tls, address) = match self.url.split_once("://") {
+            Some(("mqtt", address)) => (false, address),
+            Some(("mqtts", address)) => (true, address),
+            _ => return Err("mqtt urls must start with `mqtt://` or `mqtts://`".to_owned()),
+        };
+        let (host, port) =
+            parse_address(address.trim_end_matches('/'), if tls { 8883 } else { 1883 })?;
+
+        if self.topic.is_empty() || self.topic.contains(['+', '#']) {
+            return Err("mqtt topics can't be empty or have wildcards".to_owned());
+        }
+        if self.qos > 2 {
+            return Err("mqtt qos must be 0, 1 or 2".to_owned());
+        }
+        let password = read_secret(self.password_file.as_deref(), self.password_env.as_deref())?;
+        if password.is_some() && self.username.is_none() {
+            return Err("an mqtt password needs a username".to_owned());
+        }
+
+        Ok(Mqtt {
+            host,
+            port,
+            tls,
+            topic: self.topic,
+            qos: self.qos,
+            retain: self.retain,
+            username: self.username,
+            password,
+            client_id: self
+                .client_id
+                .unwrap_or_else(|| format!("notif_stopped-{}", std::process::id())),
+        })
+    }
+}
+
+/// Splits `host[:port]` (where IPv6 hosts are in brackets).
+fn parse_address(address: &str, default_port: u16) -> Result<(String, u16), String> {
+    let (host, port) = match address.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| "invalid mqtt broker address".to_owned())?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match address.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        },
+    };
+    if host.is_empty() {
+        return Err("mqtt urls need a host".to_owned());
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| format!("invalid mqtt port: {port}"))?,
+        None => default_port,
+    };
+    Ok((host.to_owned(), port))
+}
+
+/// An MQTT topic to publish notifications to, as JSON.
+pub struct Mqtt {
+    host: String,
+    port: u16,
+    tls: bool,
+    topic: String,
+    qos: u8,
+    retain: bool,
+    username: Option<String>,
+    password: Option<String>,
+    client_id: String,
+}
+
+/// Packet types, as they are in the first byte of a packet.
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const DISCONNECT: u8 = 14;
+
+/// The id of the (only) message that's published on each connection.
+const PACKET_ID: u16 = 1;
+
+/// The most that can be encoded as a packet's remaining length.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+impl Mqtt {
+    /// Makes a single attempt at publishing the event (with MQTT 3.1.1), waiting for the broker
+    /// to acknowledge it if the QoS is above 0.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), String> {
+        let connect = self.connect_packet()?;
+        let publish = self.publish_packet(event.to_json().to_string().as_bytes())?;
+        let mut connection = self.connect(timeout)?;
+
+        connection.write(&connect)?;
+        let connack = connection.read(CONNACK)?;
+        match connack.get(1) {
+            Some(0) => (),
+            Some(1) => return Err("the broker doesn't support MQTT 3.1.1".to_owned()),
+            Some(2) => return Err("the broker rejected the client id".to_owned()),
+            Some(3) => return Err("the broker is unavailable".to_owned()),
+            Some(4) => return Err("the broker rejected the username or password".to_owned()),
+            Some(5) => return Err("not authorized to connect to the broker".to_owned()),
+            _ => return Err("the broker sent an invalid CONNACK".to_owned()),
+        }
+
+        connection.write(&publish)?;
+        match self.qos {
+            1 => connection.read_ack(PUBACK)?,
+            2 => {
+                connection.read_ack(PUBREC)?;
+                // PUBREL has to have these flags.
+                connection.write(&encode_packet(
+                    PUBREL << 4 | 0b10,
+                    &PACKET_ID.to_be_bytes(),
+                )?)?;
+                connection.read_ack(PUBCOMP)?;
+            }
+            _ => (),
+        }
+
+        // The message has been published (as far as the QoS goes) by now.
+        drop(connection.write(&encode_packet(DISCONNECT << 4, &[])?));
+        Ok(())
+    }
+
+    fn connect_packet(&self) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT")?;
+        // The protocol level for 3.1.1.
+        body.push(4);
+        let mut flags = 0b10; // clean session
+        if self.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.password.is_some() {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        // The keep alive (in seconds), which doesn't matter for a connection this short.
+        body.extend_from_slice(&60u16.to_be_bytes());
+        put_string(&mut body, &self.client_id)?;
+        for field in [&self.username, &self.password].into_iter().flatten() {
+            put_string(&mut body, field)?;
+        }
+        encode_packet(CONNECT << 4, &body)
+    }
+
+    fn publish_packet(&self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        put_string(&mut body, &self.topic)?;
+        if self.qos > 0 {
+            body.extend_from_slice(&PACKET_ID.to_be_bytes());
+        }
+        body.extend_from_slice(payload);
+        let flags = (self.qos << 1) | u8::from(self.retain);
+        encode_packet(PUBLISH << 4 | flags, &body)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        format!(
+            "publish to {} on {}:{} (qos {}{})\n{}",
+            self.topic,
+            self.host,
+            self.port,
+            self.qos,
+            if self.retain { ", retained" } else { "" },
+            event.to_json()
+        )
+    }
+
+    pub fn destination(&self) -> String {
+        format!("{}:{} {}", self.host, self.port, self.topic)
+    }
+
+    fn connect(&self, timeout: Duration) -> Result<Connection, String> {
+        let deadline = Instant::now() + timeout;
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| format!("failed to look up {}: {e}", self.host))?
+            .next()
+            .ok_or_else(|| format!("failed to look up {}", self.host))?;
+        let socket = TcpStream::connect_timeout(&address, timeout)
+            .map_err(|e| format!("failed to connect to {}:{}: {e}", self.host, self.port))?;
+        let socket = DeadlineSocket { socket, deadline };
+
+        if !self.tls {
+            return Ok(Connection(Box::new(socket)));
+        }
+        let connector = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+        let stream = connector
+            .connect(&self.host, socket)
+            .map_err(|e| format!("tls handshake with {} failed: {e}", self.host))?;
+        Ok(Connection(Box::new(stream)))
+    }
+}
+
+/// A socket that fails once the deadline has passed, however many reads and writes it takes to
+/// get there.
+#[derive(Debug)]
+struct DeadlineSocket {
+    socket: TcpStream,
+    deadline: Instant,
+}
+
+impl DeadlineSocket {
+    /// How long is left until the deadline, failing if it's already passed.
+    fn remaining(&self) -> std::io::Result<Duration> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(remaining),
+            _ => Err(std::io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+impl Read for DeadlineSocket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.socket.set_read_timeout(Some(self.remaining()?))?;
+        self.socket.read(buf)
+    }
+}
+
+impl Write for DeadlineSocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.socket.set_write_timeout(Some(self.remaining()?))?;
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+/// A connection to the broker, which may be over TLS.
+struct Connection(Box<dyn Stream>);
+
+impl Connection {
+    fn write(&mut self, packet: &[u8]) -> Result<(), String> {
+        self.0
+            .write_all(packet)
+            .and_then(|()| self.0.flush())
+            .map_err(|e| format!("failed to send to the broker: {e}"))
+    }
+
+    /// Reads packets until one of the given type arrives (ignoring any others), returning its body.
+    fn read(&mut self, packet_type: u8) -> Result<Vec<u8>, String> {
+        loop {
+            let (first_byte, body) = read_packet(&mut self.0).map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+                    "timed out waiting for the broker".to_owned()
+                }
+                _ => format!("failed to read from the broker: {e}"),
+            })?;
+            if first_byte >> 4 == packet_type {
+                return Ok(body);
+            }
+        }
+    }
+
+    /// Reads an acknowledgement of the published message.
+    fn read_ack(&mut self, packet_type: u8) -> Result<(), String> {
+        let body = self.read(packet_type)?;
+        match body.get(..2) {
+            Some(id) if id == PACKET_ID.to_be_bytes() => Ok(()),
+            _ => Err("the broker acknowledged a different message".to_owned()),
+        }
+    }
+}
+
+/// Appends a string prefixed with its length, as MQTT encodes them.
+fn put_string(buf: &mut Vec<u8>, string: &str) -> Result<(), String> {
+    let length = u16::try_from(string.len())
+        .map_err(|_| format!("mqtt strings can't be over {} bytes", u16::MAX))?;
+    buf.extend_from_slice(&length.to_be_bytes());
+    buf.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+/// A packet with its fixed header: the first byte (the type and flags) and the remaining length.
+fn encode_packet(first_byte: u8, body: &[u8]) -> Result<Vec<u8>, String> {
+    if body.len() > MAX_REMAINING_LENGTH {
+        return Err("the message is too big for mqtt".to_owned());
+    }
+
+    let mut packet = vec![first_byte];
+    // The remaining length is 7 bits per byte, least significant first, with the top bit set on
+    // every byte but the last.
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    Ok(packet)
+}
+
+/// Reads a packet, returning its first byte and body.
+fn read_packet(stream: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    let first_byte = byte[0];
+
+    let mut length = 0;
+    for i in 0.. {
+        if i == 4 {
+            let error = "the broker sent an invalid remaining length";
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error));
+        }
+        stream.read_exact(&mut byte)?;
+        length |= usize::from(byte[0] & 0x7f) << (i * 7);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok((first_byte, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::event::ExitReason;
+
+    fn mqtt(url: &str, qos: u8, username: Option<&str>) -> Mqtt {
+        MqttConfig {
+            url: url.to_owned(),
+            topic: "t".to_owned(),
+            qos,
+            retain: qos == 1,
+            username: username.map(str::to_owned),
+            password_file: None,
+            password_env: None,
+            client_id: Some("c".to_owned()),
+        }
+        .build()
+        .unwrap()
+    }
+
+    /// Accepts a single connection and hands it to `broker` on another thread.
+    fn serve<T: Send + 'static>(
+        broker: impl FnOnce(TcpStream) -> T + Send + 'static,
+    ) -> (String, std::thread::JoinHandle<T>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("mqtt://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || broker(listener.accept().unwrap().0));
+        (url, handle)
+    }
+
+    #[test]
+    fn remaining_length() {
+        for (length, encoded) in [
+            (0, &[0x00][..]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+            (MAX_REMAINING_LENGTH, &[0xff, 0xff, 0xff, 0x7f]),
+        ] {
+            let packet = encode_packet(0x30, &vec![0; length]).unwrap();
+            assert_eq!(&packet[1..=encoded.len()], encoded, "{length}");
+            assert_eq!(packet.len(), 1 + encoded.len() + length);
+
+            let (first_byte, body) = read_packet(&mut Cursor::new(packet)).unwrap();
+            assert_eq!((first_byte, body.len()), (0x30, length));
+        }
+        assert!(encode_packet(0x30, &vec![0; MAX_REMAINING_LENGTH + 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_remaining_length() {
+        let packet = [0x30, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(read_packet(&mut Cursor::new(packet)).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let mut buf = Vec::new();
+        put_string(&mut buf, "MQTT").unwrap();
+        assert_eq!(buf, b"\x00\x04MQTT");
+        assert!(put_string(&mut buf, &"a".repeat(65_535)).is_ok());
+        assert!(put_string(&mut Vec::new(), &"a".repeat(65_536)).is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        let parse = |address| parse_address(address, 1883);
+        assert_eq!(parse("broker"), Ok(("broker".to_owned(), 1883)));
+        assert_eq!(parse("broker:1884"), Ok(("broker".to_owned(), 1884)));
+        assert_eq!(parse("[::1]"), Ok(("::1".to_owned(), 1883)));
+        assert_eq!(parse("[::1]:1884"), Ok(("::1".to_owned(), 1884)));
+        assert!(parse("").is_err());
+        assert!(parse(":1884").is_err());
+        assert!(parse("broker:port").is_err());
+        assert!(parse("[::1:1884").is_err());
+    }
+
+    #[test]
+    fn connect_packet() {
+        let packet = mqtt("mqtt://broker", 1, None).connect_packet().unwrap();
+        assert_eq!(packet, b"\x10\x0d\x00\x04MQTT\x04\x02\x00\x3c\x00\x01c");
+
+        let mut mqtt = mqtt("mqtt://broker", 1, Some("u"));
+        mqtt.password = Some("p".to_owned());
+        let packet = mqtt.connect_packet().unwrap();
+        assert_eq!(
+            packet,
+            b"\x10\x13\x00\x04MQTT\x04\xc2\x00\x3c\x00\x01c\x00\x01u\x00\x01p"
+        );
+    }
+
+    #[test]
+    fn publish_packet() {
+        let packet = mqtt("mqtt://broker", 0, None)
+            .publish_packet(b"hi")
+            .unwrap();
+        assert_eq!(packet, b"\x30\x05\x00\x01thi");
+        // With QoS 1 (and retained), it has a packet id.
+        let packet = mqtt("mqtt://broker", 1, None)
+            .publish_packet(b"hi")
+            .unwrap();
+        assert_eq!(packet, b"\x33\x07\x00\x01t\x00\x01hi");
+    }
+
+    #[test]
+    fn publishes_with_qos_2() {
+        let (url, broker) = serve(|mut stream| {
+            let mut types = Vec::new();
+            let mut payload = Vec::new();
+            loop {
+                let (first_byte, body) = read_packet(&mut stream).unwrap();
+                types.push(first_byte >> 4);
+                let reply: &[u8] = match first_byte >> 4 {
+                    CONNECT => &[0x20, 2, 0, 0],
+                    PUBLISH => {
+                        payload = body[5..].to_vec();
+                        &[0x50, 2, 0, 1]
+                    }
+                    PUBREL => &[0x70, 2, 0, 1],
+                    _ => break,
+                };
+                stream.write_all(reply).unwrap();
+            }
+            (types, payload)
+        });
+
+        let event = StopEvent::example(ExitReason::Exited(0));
+        mqtt(&url, 2, None)
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let (types, payload) = broker.join().unwrap();
+        assert_eq!(types, [CONNECT, PUBLISH, PUBREL, DISCONNECT]);
+        assert_eq!(payload, event.to_json().to_string().as_bytes());
+    }
+
+    #[test]
+    fn acknowledgements_need_the_packet_id() {
+        let (url, broker) = serve(|mut stream| {
+            read_packet(&mut stream).unwrap();
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            read_packet(&mut stream).unwrap();
+            stream.write_all(&[0x40, 2, 0, 9]).unwrap();
+        });
+
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let result = mqtt(&url, 1, None).send(&event, Duration::from_secs(5));
+        assert_eq!(
+            result,
+            Err("the broker acknowledged a different message".to_owned())
+        );
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn slow_brokers_time_out() {
+        // The CONNACK is sent a byte at a time, slower than the timeout allows for.
+        let (url, broker) = serve(|mut stream| {
+            read_packet(&mut stream).unwrap();
+            for byte in [0x20, 2, 0, 0] {
+                std::thread::sleep(Duration::from_millis(150));
+                if stream.write_all(&[byte]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let start = Instant::now();
+        let result = mqtt(&url, 1, None).send(&event, Duration::from_millis(300));
+        assert_eq!(result, Err("timed out waiting for the broker".to_owned()));
+        assert!(start.elapsed() < Duration::from_millis(450));
+        broker.join().unwrap();
+    }
+}
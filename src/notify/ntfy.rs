@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::{read_secret, send_request, SendError};
+use crate::event::StopEvent;
+
+/// The options for an ntfy topic, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NtfyConfig {
+    /// The topic's url, e.g. `https://ntfy.sh/my-builds`.
+    pub url: String,
+    /// From 1 (min) to 5 (max). By default, failures are sent with high priority (4) and
+    /// everything else with the default priority (3).
+    pub priority: Option<u8>,
+    /// Tags (or emoji shortcodes) to add to the ones for the outcome.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub token_file: Option<PathBuf>,
+    /// The environment variable to read the access token from (if there's no file).
+    pub token_env: Option<String>,
+}
+
+impl NtfyConfig {
+    /// Validates the options and loads the access token.
+    pub fn build(self) -> Result<Ntfy, String> {
+        if !self.url.starts_with("http") {
+            return Err("ntfy topic urls must be http(s) urls".to_owned());
+        }
+        if self.priority.is_some_and(|p| !(1..=5).contains(&p)) {
+            return Err("ntfy priorities must be from 1 to 5".to_owned());
+        }
+
+        Ok(Ntfy {
+            url: self.url,
+            priority: self.priority,
+            tags: self.tags,
+            token: read_secret(self.token_file.as_deref(), self.token_env.as_deref())?,
+        })
+    }
+}
+
+/// An ntfy topic to publish notifications to.
+pub struct Ntfy {
+    url: String,
+    priority: Option<u8>,
+    tags: Vec<String>,
+    token: Option<String>,
+}
+
+impl Ntfy {
+    /// Makes a single attempt at publishing the notification.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let mut request = minreq::post(self.url.as_str())
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(event.report());
+        for (name, value) in self.headers(event) {
+            request = request.with_header(name, value);
+        }
+        if let Some(token) = &self.token {
+            request = request.with_header("Authorization", format!("Bearer {token}"));
+        }
+
+        send_request(request, timeout, &[]).map(drop)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        let headers: String = self
+            .headers(event)
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\n"))
+            .collect();
+        format!("POST {}\n{headers}\n{}", self.url, event.report())
+    }
+
+    pub fn destination(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The `Title`, `Priority` and `Tags` headers for an event.
+    fn headers(&self, event: &StopEvent) -> Vec<(&'static str, String)> {
+        let (priority, outcome_tag) = match event.succeeded() {
+            Some(true) => (3, "white_check_mark"),
+            Some(false) => (4, "x"),
+            None => (3, "stop_sign"),
+        };
+        let tags: Vec<&str> = std::iter::once(outcome_tag)
+            .chain(self.tags.iter().map(String::as_str))
+            .collect();
+
+        vec![
+            ("Title", event.title()),
+            ("Priority", self.priority.unwrap_or(priority).to_string()),
+            ("Tags", tags.join(",")),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+    use crate::notify::serve_once;
+
+    fn ntfy(url: &str, priority: Option<u8>, tags: &[&str]) -> Ntfy {
+        NtfyConfig {
+            url: url.to_owned(),
+            priority,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            token_file: None,
+            token_env: None,
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn headers_depend_on_the_outcome() {
+        let ntfy = ntfy("https://ntfy.sh/builds", None, &[]);
+        let headers = |reason| ntfy.headers(&StopEvent::example(reason));
+
+        assert_eq!(
+            headers(ExitReason::Exited(0)),
+            [
+                ("Title", "make stopped on host".to_owned()),
+                ("Priority", "3".to_owned()),
+                ("Tags", "white_check_mark".to_owned()),
+            ]
+        );
+        assert_eq!(
+            headers(ExitReason::Exited(1))[1..],
+            [("Priority", "4".to_owned()), ("Tags", "x".to_owned()),]
+        );
+        assert_eq!(
+            headers(ExitReason::Unknown)[1..],
+            [
+                ("Priority", "3".to_owned()),
+                ("Tags", "stop_sign".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn priority_and_tags_can_be_set() {
+        let ntfy = ntfy("https://ntfy.sh/builds", Some(5), &["ci", "rocket"]);
+        let headers = ntfy.headers(&StopEvent::example(ExitReason::Exited(1)));
+        assert_eq!(
+            headers[1..],
+            [
+                ("Priority", "5".to_owned()),
+                ("Tags", "x,ci,rocket".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let config = |url: &str, priority| NtfyConfig {
+            url: url.to_owned(),
+            priority,
+            tags: Vec::new(),
+            token_file: None,
+            token_env: None,
+        };
+        assert!(config("ntfy.sh/builds", None).build().is_err());
+        assert!(config("https://ntfy.sh/builds", Some(0)).build().is_err());
+        assert!(config("https://ntfy.sh/builds", Some(6)).build().is_err());
+    }
+
+    #[test]
+    fn publishes_the_report() {
+        let (url, server) = serve_once(200, "{}");
+        let event = StopEvent::example(ExitReason::Exited(0));
+        ntfy(&format!("{url}/builds"), None, &[])
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /builds HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nTitle: make stopped on host\r\n"));
+        assert!(request.contains("\r\nTags: white_check_mark\r\n"));
+        assert!(request.ends_with(&format!("\r\n\r\n{}", event.report())));
+    }
+
+    #[test]
+    fn errors_include_the_response() {
+        let (url, server) = serve_once(403, "{\"error\":\"forbidden\"}");
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let result = ntfy(&url, None, &[]).send(&event, Duration::from_secs(5));
+        server.join().unwrap();
+        assert_eq!(
+            result,
+            Err(SendError::permanent(
+                "server responded with status 403 Status: {\"error\":\"forbidden\"}".to_owned()
+            ))
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::{percent_encode, read_secret, send_request, truncate, SendError};
+use crate::event::{ExitReason, StopEvent};
+
+const API_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// Pushover's limits on the lengths of titles and messages.
+const MAX_TITLE_CHARS: usize = 250;
+const MAX_MESSAGE_CHARS: usize = 1024;
+
+/// How often (in seconds) an emergency notification is repeated until it's acknowledged, and for
+/// how long.
+const EMERGENCY_RETRY: u32 = 60;
+const EMERGENCY_EXPIRE: u32 = 3600;
+
+/// The options for a Pushover target, as given in the config file (or in the environment).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PushoverConfig {
+    pub token_file: Option<PathBuf>,
+    /// The environment variable to read the app token from (if there's no file).
+    pub token_env: Option<String>,
+    /// The user (or group) key to send to.
+    pub user: Option<String>,
+    /// The environment variable to read the user key from (if it isn't given directly).
+    pub user_env: Option<String>,
+    /// From -2 (lowest) to 1 (high), 0 by default.
+    pub priority: Option<i8>,
+    /// The priority for processes that failed, 1 by default.
+    pub failure_priority: Option<i8>,
+    /// Send crashes (processes killed by a signal) with emergency priority, which repeats the
+    /// notification until it's acknowledged.
+    #[serde(default)]
+    pub emergency_on_crash: bool,
+    pub sound: Option<String>,
+    /// Where to send messages to, instead of Pushover's API.
+    pub api_url: Option<String>,
+}
+
+impl PushoverConfig {
+    /// Validates the options and loads the app token and user key.
+    pub fn build(self) -> Result<Pushover, String> {
+        let token = read_secret(self.token_file.as_deref(), self.token_env.as_deref())?
+            .ok_or_else(|| match &self.token_env {
+                Some(var) => format!("an app token is needed (set `{var}` or use a token file)"),
+                None => "an app token file is needed".to_owned(),
+            })?;
+        let user = match (self.user, &self.user_env) {
+            (Some(user), _) => user,
+            (None, Some(var)) => std::env::var(var)
+                .ok()
+                .filter(|user| !user.is_empty())
+                .ok_or_else(|| format!("a user key is needed (set `{var}`)"))?,
+            (None, None) => return Err("a user key is needed".to_owned()),
+        };
+
+        let priority = self.priority.unwrap_or(0);
+        let failure_priority = self.failure_priority.unwrap_or(1);
+        if ![priority, failure_priority]
+            .iter()
+            .all(|p| (-2..=1).contains(p))
+        {
+            return Err("pushover priorities must be from -2 to 1".to_owned());
+        }
+
+        let api_url = self.api_url.unwrap_or_else(|| API_URL.to_owned());
+        if !api_url.starts_with("http") {
+            return Err("pushover api urls must be http(s) urls".to_owned());
+        }
+
+        Ok(Pushover {
+            api_url,
+            token,
+            user,
+            priority,
+            failure_priority,
+            emergency_on_crash: self.emergency_on_crash,
+            sound: self.sound,
+        })
+    }
+}
+
+/// A Pushover user (or group) to send notifications to.
+pub struct Pushover {
+    api_url: String,
+    token: String,
+    user: String,
+    priority: i8,
+    failure_priority: i8,
+    emergency_on_crash: bool,
+    sound: Option<String>,
+}
+
+impl Pushover {
+    /// Makes a single attempt at sending the notification.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let mut fields = vec![("token", self.token.clone()), ("user", self.user.clone())];
+        fields.extend(self.fields(event));
+
+        let request = minreq::post(self.api_url.as_str())
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_body(form_encode(&fields));
+        send_request(request, timeout, &[]).map(drop)
+    }
+
+    /// The fields that would be sent, without the token and user key.
+    pub fn preview(&self, event: &StopEvent) -> String {
+        let fields: Vec<String> = self
+            .fields(event)
+            .iter()
+            .map(|(name, value)| format!("{name}={value:?}"))
+            .collect();
+        format!("POST {} {}", self.api_url, fields.join(" "))
+    }
+
+    pub fn destination(&self) -> String {
+        format!("{} {}", self.api_url, self.user)
+    }
+
+    /// The fields describing an event.
+    fn fields(&self, event: &StopEvent) -> Vec<(&'static str, String)> {
+        let crashed = matches!(event.exit_reason, ExitReason::Signaled { .. });
+        let priority = match event.succeeded() {
+            Some(false) if crashed && self.emergency_on_crash => 2,
+            Some(false) => self.failure_priority,
+            _ => self.priority,
+        };
+
+        let mut fields = vec![
+            ("title", truncate(&event.title(), MAX_TITLE_CHARS)),
+            ("message", truncate(&event.report(), MAX_MESSAGE_CHARS)),
+            ("priority", priority.to_string()),
+        ];
+        if priority == 2 {
+            fields.push(("retry", EMERGENCY_RETRY.to_string()));
+            fields.push(("expire", EMERGENCY_EXPIRE.to_string()));
+        }
+        if let Some(sound) = &self.sound {
+            fields.push(("sound", sound.clone()));
+        }
+        fields
+    }
+}
+
+/// Encodes fields as `application/x-www-form-urlencoded`.
+fn form_encode(fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::serve_once;
+
+    fn pushover(api_url: Option<String>) -> Pushover {
+        std::env::set_var("NOTIF_TEST_PUSHOVER_TOKEN", "token");
+        PushoverConfig {
+            token_file: None,
+            token_env: Some("NOTIF_TEST_PUSHOVER_TOKEN".to_owned()),
+            user: Some("user".to_owned()),
+            user_env: None,
+            priority: None,
+            failure_priority: None,
+            emergency_on_crash: true,
+            sound: None,
+            api_url,
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn form_encoding() {
+        let fields = [
+            ("title", "a b&c=d".to_owned()),
+            ("priority", "-1".to_owned()),
+        ];
+        assert_eq!(form_encode(&fields), "title=a%20b%26c%3Dd&priority=-1");
+    }
+
+    #[test]
+    fn long_fields_are_truncated_to_the_limits() {
+        let mut event = StopEvent::example(ExitReason::Exited(0));
+        event.process_name = "x".repeat(300);
+        event.description = "y".repeat(2000);
+        let fields = pushover(None).fields(&event);
+        assert_eq!(fields[0].1.chars().count(), MAX_TITLE_CHARS);
+        assert_eq!(fields[1].1.chars().count(), MAX_MESSAGE_CHARS);
+    }
+
+    #[test]
+    fn crashes_are_emergencies() {
+        let signaled = ExitReason::Signaled {
+            signal: 11,
+            core_dumped: true,
+        };
+        let fields = pushover(None).fields(&StopEvent::example(signaled));
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        assert!(fields.contains(&("priority", "2")));
+        assert!(fields.contains(&("retry", "60")));
+        assert!(fields.contains(&("expire", "3600")));
+    }
+
+    #[test]
+    fn sends_a_form() {
+        let (url, server) = serve_once(200, "{\"status\":1}");
+        let event = StopEvent::example(ExitReason::Exited(1));
+        pushover(Some(url))
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(request.contains("application/x-www-form-urlencoded"));
+        assert!(request.contains("token=token&user=user&title=make%20stopped%20on%20host&"));
+        assert!(request.ends_with("&priority=1"));
+    }
+}
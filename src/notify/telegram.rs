@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{read_secret, send_request, truncate, SendError};
+use crate::event::StopEvent;
+
+const API_URL: &str = "https://api.telegram.org";
+
+/// Telegram's limit on the length of a message, which it rejects messages over.
+const MAX_TEXT_CHARS: usize = 4096;
+/// How much of the message the description (which can have the command line in it) can take up.
+const MAX_DESCRIPTION_CHARS: usize = 1024;
+
+/// The options for a Telegram chat, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub token_file: Option<PathBuf>,
+    /// The environment variable to read the bot token from (if there's no file).
+    pub token_env: Option<String>,
+    /// The chat's id (as a string), or `@name` for public channels.
+    pub chat_id: String,
+    /// The Bot API's base url, instead of Telegram's.
+    pub api_url: Option<String>,
+}
+
+impl TelegramConfig {
+    /// Validates the options and loads the bot token.
+    pub fn build(self) -> Result<Telegram, String> {
+        let token = read_secret(self.token_file.as_deref(), self.token_env.as_deref())?
+            .ok_or_else(|| match &self.token_env {
+                Some(var) => format!("a bot token is needed (set `{var}` or use a token file)"),
+                None => "a bot token file is needed".to_owned(),
+            })?;
+        if self.chat_id.is_empty() {
+            return Err("a chat id is needed".to_owned());
+        }
+        let api_url = self.api_url.unwrap_or_else(|| API_URL.to_owned());
+        if !api_url.starts_with("http") {
+            return Err("telegram api urls must be http(s) urls".to_owned());
+        }
+
+        Ok(Telegram {
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            token,
+            chat_id: self.chat_id,
+        })
+    }
+}
+
+/// A Telegram chat that a bot sends notifications to.
+pub struct Telegram {
+    api_url: String,
+    token: String,
+    chat_id: String,
+}
+
+impl Telegram {
+    /// Makes a single attempt at sending the message.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.token);
+        let request = minreq::post(url)
+            .with_header("Content-Type", "application/json")
+            .with_body(self.body(event).to_string());
+        send_request(request, timeout, &[]).map(drop)
+    }
+
+    /// What would be sent, without the bot token (which is part of the url).
+    pub fn preview(&self, event: &StopEvent) -> String {
+        format!(
+            "POST {}/bot<token>/sendMessage\n{}",
+            self.api_url,
+            self.body(event)
+        )
+    }
+
+    pub fn destination(&self) -> String {
+        format!("{} {}", self.api_url, self.chat_id)
+    }
+
+    fn body(&self, event: &StopEvent) -> serde_json::Value {
+        let mut text = format!(
+            "*{}*\n{}\n",
+            escape(&event.title()),
+            escape_within(&event.description, MAX_DESCRIPTION_CHARS)
+        );
+
+        // The command line is the only detail that can be long, so it gets whatever room is left.
+        let details = event.details();
+        let line = |label: &str, value: &str| format!("\n*{label}:* {value}");
+        let others: usize = details
+            .iter()
+            .filter(|(label, _)| *label != "Command")
+            .map(|(label, value)| line(label, &escape(value)).chars().count())
+            .sum();
+        let room = MAX_TEXT_CHARS
+            .saturating_sub(text.chars().count() + others + line("Command", "").chars().count());
+        for (label, value) in details {
+            let value = match label {
+                "Command" => escape_within(&value, room),
+                _ => escape(&value),
+            };
+            text.push_str(&line(label, &value));
+        }
+
+        json!({
+            "chat_id": self.chat_id,
+            "text": text,
+            "parse_mode": "MarkdownV2",
+        })
+    }
+}
+
+/// Escapes the characters that are special in Telegram's MarkdownV2.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes text, truncating it so that it's at most `max_chars` chars once it's escaped (without
+/// cutting an escape sequence in half).
+fn escape_within(text: &str, max_chars: usize) -> String {
+    let mut keep = max_chars;
+    loop {
+        let escaped = escape(&truncate(text, keep));
+        match escaped.chars().count().checked_sub(max_chars) {
+            // A char is escaped to at most 2, so a char has to go for every 2 that are over.
+            Some(over @ 1..) if keep > 0 => keep = keep.saturating_sub(over.div_ceil(2)),
+            Some(1..) => return String::new(),
+            _ => return escaped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+    use crate::notify::serve_once;
+
+    fn telegram(api_url: Option<String>) -> Telegram {
+        std::env::set_var("NOTIF_TEST_TELEGRAM_TOKEN", "123:abc");
+        TelegramConfig {
+            token_file: None,
+            token_env: Some("NOTIF_TEST_TELEGRAM_TOKEN".to_owned()),
+            chat_id: "-100123".to_owned(),
+            api_url,
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn markdown_escaping() {
+        assert_eq!(escape("plain text"), "plain text");
+        assert_eq!(
+            escape("make -j8 (pid 1) exited!"),
+            "make \\-j8 \\(pid 1\\) exited\\!"
+        );
+        assert_eq!(
+            escape(r"a_b*c[d]~`>#+=|{}.\"),
+            r"a\_b\*c\[d\]\~\`\>\#\+\=\|\{\}\.\\"
+        );
+    }
+
+    #[test]
+    fn message_body() {
+        let body = telegram(None).body(&StopEvent::example(ExitReason::Exited(0)));
+        assert_eq!(body["chat_id"], "-100123");
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+        let text = body["text"].as_str().unwrap();
+        assert!(text.starts_with(
+            "*make stopped on host*\nmake \\(pid 4242\\) exited with code 0\n\n*Host:* host\n"
+        ));
+        assert!(text.contains("\n*Command:* make \\-j 8\n"));
+    }
+
+    #[test]
+    fn long_messages_are_truncated() {
+        let mut event = StopEvent::example(ExitReason::Exited(0));
+        let arguments = std::iter::repeat_n("-x.".to_owned(), 3000);
+        event.command_line = Some(
+            std::iter::once("python".to_owned())
+                .chain(arguments)
+                .collect(),
+        );
+        event.description = "(".repeat(5000);
+
+        let body = telegram(None).body(&event);
+        let text = body["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_TEXT_CHARS);
+        let (_, description) = text.split_once('\n').unwrap();
+        let (description, _) = description.split_once('\n').unwrap();
+        assert_eq!(description, format!("{}…", "\\(".repeat(511)));
+        let (_, command) = text.split_once("\n*Command:* ").unwrap();
+        let (command, _) = command.split_once('\n').unwrap();
+        assert!(command.starts_with("python \\-x\\. \\-x\\. "));
+        // No escape sequence is cut in half.
+        assert!(command.ends_with("\\-…"));
+        assert!(text.contains("\n*Runtime:* 1m 30s"));
+    }
+
+    #[test]
+    fn sends_to_the_bot_api() {
+        let (url, server) = serve_once(200, "{\"ok\":true}");
+        let event = StopEvent::example(ExitReason::Exited(0));
+        telegram(Some(format!("{url}/")))
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /bot123:abc/sendMessage HTTP/1.1\r\n"));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body, telegram(None).body(&event));
+    }
+}
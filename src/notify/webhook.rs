@@ -0,0 +1,380 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use super::{chat, read_secret, send_request, SendError};
+use crate::event::StopEvent;
+use crate::template::Template;
+
+/// The HTTP method to send the webhook with.
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl From<Method> for minreq::Method {
+    fn from(method: Method) -> Self {
+        match method {
+            Method::Get => minreq::Method::Get,
+            Method::Post => minreq::Method::Post,
+            Method::Put => minreq::Method::Put,
+            Method::Patch => minreq::Method::Patch,
+            Method::Delete => minreq::Method::Delete,
+        }
+    }
+}
+
+/// What to send as the webhook's body (unless there's a template).
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Body {
+    /// A Slack or Discord message if the url is for one of those, or JSON otherwise
+    Auto,
+    /// A JSON object describing the stopped process
+    Json,
+    /// A message for a Slack incoming webhook
+    Slack,
+    /// A message for a Discord webhook
+    Discord,
+    /// An empty body
+    None,
+}
+
+impl Body {
+    /// The body to use for a url, which is only different for `Auto`.
+    fn for_url(self, url: &str) -> Self {
+        if !matches!(self, Self::Auto) {
+            return self;
+        }
+
+        let host = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        match host {
+            "hooks.slack.com" => Self::Slack,
+            "discord.com" | "discordapp.com" | "ptb.discord.com" | "canary.discord.com" => {
+                Self::Discord
+            }
+            _ => Self::Json,
+        }
+    }
+
+    /// The body to send for an event, if any.
+    pub fn render(self, template: Option<&Template>, event: &StopEvent) -> Option<String> {
+        match (template, self) {
+            (Some(template), _) => Some(template.render(event)),
+            // Without a url, there's nothing to go on.
+            (None, Body::Auto | Body::Json) => Some(event.to_json().to_string()),
+            (None, Body::Slack) => Some(chat::slack(event).to_string()),
+            (None, Body::Discord) => Some(chat::discord(event).to_string()),
+            (None, Body::None) => None,
+        }
+    }
+}
+
+/// Credentials sent in the `Authorization` header. Secrets are never printed.
+pub enum Auth {
+    Bearer(String),
+    Basic { user: String, password: String },
+}
+
+impl Auth {
+    fn header_value(&self) -> String {
+        match self {
+            Self::Bearer(token) => format!("Bearer {token}"),
+            Self::Basic { user, password } => {
+                let encoded =
+                    base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+                format!("Basic {encoded}")
+            }
+        }
+    }
+}
+
+/// The options for a webhook, as given in the config file (or on the command line).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: Method,
+    /// Formatted as `Name: value`.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    pub bearer_token_file: Option<PathBuf>,
+    /// The environment variable to read the bearer token from (if there's no file).
+    pub bearer_token_env: Option<String>,
+    pub basic_auth_user: Option<String>,
+    pub basic_auth_password_file: Option<PathBuf>,
+    /// The environment variable to read the basic auth password from (if there's no file).
+    pub basic_auth_password_env: Option<String>,
+    #[serde(default = "default_body")]
+    pub body: Body,
+    pub template: Option<PathBuf>,
+    #[serde(default)]
+    pub expect_status: Vec<i32>,
+}
+
+fn default_method() -> Method {
+    Method::Post
+}
+
+fn default_body() -> Body {
+    Body::Auto
+}
+
+impl WebhookConfig {
+    /// Validates the options and loads the secrets and template they refer to.
+    pub fn build(self) -> Result<Webhook, String> {
+        if !self.url.starts_with("http") {
+            return Err("webhook urls must be http(s) urls".to_owned());
+        }
+
+        let headers = self
+            .headers
+            .iter()
+            .filter(|header| !header.trim().is_empty())
+            .map(|header| parse_header(header))
+            .collect::<Result<_, _>>()?;
+
+        let bearer_token = read_secret(
+            self.bearer_token_file.as_deref(),
+            self.bearer_token_env.as_deref(),
+        )?;
+        let auth = match (self.basic_auth_user, bearer_token) {
+            (Some(user), _) => {
+                let password = read_secret(
+                    self.basic_auth_password_file.as_deref(),
+                    self.basic_auth_password_env.as_deref(),
+                )?
+                .ok_or_else(|| match &self.basic_auth_password_env {
+                    Some(var) => {
+                        format!("basic auth needs a password (set `{var}` or use a password file)")
+                    }
+                    None => "basic auth needs a password file".to_owned(),
+                })?;
+                Some(Auth::Basic { user, password })
+            }
+            (None, Some(token)) => Some(Auth::Bearer(token)),
+            (None, None) => None,
+        };
+
+        Ok(Webhook {
+            body: self.body.for_url(&self.url),
+            url: self.url,
+            method: self.method,
+            headers,
+            auth,
+            template: self.template.as_deref().map(Template::load).transpose()?,
+            expected_statuses: self.expect_status,
+        })
+    }
+}
+
+/// A webhook to send notifications to.
+pub struct Webhook {
+    url: String,
+    method: Method,
+    headers: Vec<(String, String)>,
+    auth: Option<Auth>,
+    body: Body,
+    template: Option<Template>,
+    /// The status codes that count as success. If this is empty, any 2xx status does.
+    expected_statuses: Vec<i32>,
+}
+
+impl Webhook {
+    /// Makes a single attempt at sending the webhook. Responses with an unexpected status count as
+    /// failures.
+    pub fn send(&self, event: &StopEvent, timeout: Duration) -> Result<(), SendError> {
+        // minreq's header names are case-sensitive, so the defaults are left out when they're
+        // given with any case instead of being overridden.
+        let is_given = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(given, _)| given.eq_ignore_ascii_case(name))
+        };
+
+        let mut request = minreq::Request::new(self.method.into(), self.url.as_str());
+        if let Some(body) = self.body.render(self.template.as_ref(), event) {
+            if !is_given("Content-Type") {
+                let content_type = self
+                    .template
+                    .as_ref()
+                    .map_or("application/json", Template::content_type);
+                request = request.with_header("Content-Type", content_type);
+            }
+            request = request.with_body(body);
+        }
+        if let Some(auth) = self.auth.as_ref().filter(|_| !is_given("Authorization")) {
+            request = request.with_header("Authorization", auth.header_value());
+        }
+        for (name, value) in &self.headers {
+            request = request.with_header(name, value);
+        }
+
+        send_request(request, timeout, &self.expected_statuses).map(drop)
+    }
+
+    pub fn preview(&self, event: &StopEvent) -> String {
+        self.body
+            .render(self.template.as_ref(), event)
+            .unwrap_or_else(|| "(empty body)".to_owned())
+    }
+
+    pub fn destination(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// Parses a header given as `Name: value`.
+///
+/// The error doesn't include the header, since its value could be a secret.
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err("headers must be formatted as `Name: value`".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+    use crate::notify::serve_once;
+
+    fn config(url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            method: Method::Post,
+            headers: Vec::new(),
+            bearer_token_file: None,
+            bearer_token_env: None,
+            basic_auth_user: None,
+            basic_auth_password_file: None,
+            basic_auth_password_env: None,
+            body: Body::Auto,
+            template: None,
+            expect_status: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            parse_header("X-Token: a:b "),
+            Ok(("X-Token".to_owned(), "a:b".to_owned()))
+        );
+        assert_eq!(
+            parse_header("X-Empty:"),
+            Ok(("X-Empty".to_owned(), String::new()))
+        );
+        assert!(parse_header("X-Token").is_err());
+        assert!(parse_header(" : value").is_err());
+    }
+
+    #[test]
+    fn sends_json_with_auth_and_headers() {
+        let (url, server) = serve_once(204, "");
+        std::env::set_var("NOTIF_TEST_WEBHOOK_PASSWORD", "secret");
+        let mut config = config(format!("{url}/hook"));
+        config.method = Method::Put;
+        config.headers = vec!["X-Source: notif_stopped".to_owned(), " ".to_owned()];
+        config.basic_auth_user = Some("user".to_owned());
+        config.basic_auth_password_env = Some("NOTIF_TEST_WEBHOOK_PASSWORD".to_owned());
+        let event = StopEvent::example(ExitReason::Exited(0));
+        config
+            .build()
+            .unwrap()
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("PUT /hook HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(request.contains("\r\nAuthorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+        assert!(request.contains("\r\nX-Source: notif_stopped\r\n"));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+            event.to_json()
+        );
+    }
+
+    #[test]
+    fn given_headers_replace_the_defaults_in_any_case() {
+        let (url, server) = serve_once(204, "");
+        std::env::set_var("NOTIF_TEST_WEBHOOK_TOKEN", "secret");
+        let mut config = config(url);
+        config.headers = vec![
+            "content-type: text/xml".to_owned(),
+            "AUTHORIZATION: Token other".to_owned(),
+        ];
+        config.bearer_token_env = Some("NOTIF_TEST_WEBHOOK_TOKEN".to_owned());
+        let event = StopEvent::example(ExitReason::Exited(0));
+        config
+            .build()
+            .unwrap()
+            .send(&event, Duration::from_secs(5))
+            .unwrap();
+
+        let request = server.join().unwrap().to_ascii_lowercase();
+        assert_eq!(request.matches("\r\ncontent-type: ").count(), 1);
+        assert!(request.contains("\r\ncontent-type: text/xml\r\n"));
+        assert_eq!(request.matches("\r\nauthorization: ").count(), 1);
+        assert!(request.contains("\r\nauthorization: token other\r\n"));
+    }
+
+    #[test]
+    fn unexpected_statuses_fail() {
+        let (url, server) = serve_once(200, "");
+        let mut config = config(url);
+        config.expect_status = vec![201];
+        let event = StopEvent::example(ExitReason::Exited(0));
+        let result = config.build().unwrap().send(&event, Duration::from_secs(5));
+        server.join().unwrap();
+        assert_eq!(
+            result,
+            Err(SendError::permanent(
+                "server responded with status 200 Status".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn bodies_for_urls() {
+        let body = |url| match Body::Auto.for_url(url) {
+            Body::Slack => "slack",
+            Body::Discord => "discord",
+            Body::Json => "json",
+            _ => "other",
+        };
+        assert_eq!(body("https://hooks.slack.com/services/T0/B0/x"), "slack");
+        assert_eq!(body("https://discord.com/api/webhooks/1/x"), "discord");
+        assert_eq!(body("https://discordapp.com/api/webhooks/1/x"), "discord");
+        assert_eq!(
+            body("https://canary.discord.com/api/webhooks/1/x"),
+            "discord"
+        );
+        assert_eq!(body("https://hooks.slack.com:443/services/x"), "slack");
+        assert_eq!(body("https://example.com/hooks.slack.com"), "json");
+        assert_eq!(body("https://notdiscord.com/api/webhooks/1/x"), "json");
+        assert!(matches!(
+            Body::None.for_url("https://hooks.slack.com/x"),
+            Body::None
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::StopEvent;
+use crate::notify::Target;
+
+/// Notifications that couldn't be delivered, saved on disk so they can be sent later.
+///
+/// Each one is a JSON file holding its stop event and the targets it still has to be sent to, named
+/// so that sorting by name sorts by when the process stopped. Notifications are built when they're
+/// finally sent (with the event's original times), so nothing secret (like auth headers) is saved.
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    /// The outbox in `$XDG_STATE_HOME/notif_stopped/outbox` (`~/.local/state` by default, or
+    /// `%LOCALAPPDATA%` on Windows). The directory is only created once something is saved.
+    pub fn open() -> Result<Self, String> {
+        let state_dir = std::env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(default_state_dir)
+            .ok_or_else(|| "failed to find a directory for the outbox".to_owned())?;
+
+        Ok(Self {
+            dir: state_dir.join("notif_stopped").join("outbox"),
+        })
+    }
+
+    /// Saves an event to be sent to the targets later, returning the file it was saved to.
+    pub fn save(&self, event: &StopEvent, targets: Vec<PendingTarget>) -> Result<PathBuf, String> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {e}", self.dir.display()))?;
+
+        let millis = event
+            .stopped_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = self
+            .dir
+            .join(format!("{millis:020}-{:08x}.json", fastrand::u32(..)));
+        write(&path, &Pending { event, targets })?;
+        Ok(path)
+    }
+
+    /// The saved notifications (oldest first) along with the files they're in.
+    pub fn entries(&self) -> Result<Vec<(PathBuf, Pending<StopEvent>)>, String> {
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("failed to read {}: {e}", self.dir.display())),
+        };
+
+        let mut paths: Vec<PathBuf> = dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let pending = std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                Ok((path, pending))
+            })
+            .collect()
+    }
+
+    /// Sends the saved notifications in order, removing each one once it's been sent to all of its
+    /// targets. This stops at the first one that fails (so they stay in order), returning how many
+    /// were sent before that.
+    ///
+    /// `send` sends an event to the targets that are configured, returning the targets that it
+    /// still has to be sent to: on success, the ones that aren't configured (which are kept for a
+    /// later run that has them), and on failure, those along with the ones that failed.
+    pub fn flush(
+        &self,
+        mut send: impl FnMut(
+            &StopEvent,
+            &[PendingTarget],
+        ) -> Result<Vec<PendingTarget>, (Vec<PendingTarget>, String)>,
+    ) -> Result<Flushed, (usize, String)> {
+        let mut flushed = Flushed { sent: 0, kept: 0 };
+
+        for (path, pending) in self.entries().map_err(|e| (0, e))? {
+            let (targets, error) = match send(&pending.event, &pending.targets) {
+                Ok(targets) if targets.is_empty() => {
+                    std::fs::remove_file(&path).map_err(|e| {
+                        (
+                            flushed.sent + 1,
+                            format!("failed to remove {}: {e}", path.display()),
+                        )
+                    })?;
+                    flushed.sent += 1;
+                    continue;
+                }
+                Ok(targets) => (targets, None),
+                Err((targets, e)) => (targets, Some(e)),
+            };
+
+            // It only has to be rewritten if some of its targets were sent to.
+            if targets.len() != pending.targets.len() {
+                let pending = Pending {
+                    event: &pending.event,
+                    targets,
+                };
+                if let Err(write_error) = write(&path, &pending) {
+                    let e = match error {
+                        Some(e) => format!("{e} (and {write_error})"),
+                        None => write_error,
+                    };
+                    return Err((flushed.sent, e));
+                }
+            }
+            match error {
+                Some(e) => return Err((flushed.sent, e)),
+                None => flushed.kept += 1,
+            }
+        }
+
+        Ok(flushed)
+    }
+}
+
+/// What flushing the outbox did.
+pub struct Flushed {
+    /// How many notifications were sent to all of their targets (and removed).
+    pub sent: usize,
+    /// How many are still waiting for targets that aren't configured.
+    pub kept: usize,
+}
+
+/// A notification in the outbox.
+#[derive(Serialize, Deserialize)]
+pub struct Pending<E> {
+    pub event: E,
+    /// The targets that it still has to be sent to.
+    pub targets: Vec<PendingTarget>,
+}
+
+/// A target that a notification still has to be sent to.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingTarget {
+    /// The target's name when the notification was saved, for output.
+    pub name: String,
+    /// The target's key, which is how it's found again (since names can change between runs).
+    pub key: String,
+}
+
+impl PendingTarget {
+    pub fn new(target: &Target) -> Self {
+        Self {
+            name: target.name.clone(),
+            key: target.key(),
+        }
+    }
+}
+
+fn write(path: &Path, pending: &Pending<&StopEvent>) -> Result<(), String> {
+    let json = serde_json::to_string(pending).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+#[cfg(windows)]
+fn default_state_dir() -> Option<PathBuf> {
+    std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn default_state_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+
+    fn target(name: &str) -> PendingTarget {
+        PendingTarget {
+            name: name.to_owned(),
+            key: format!("{name}-key"),
+        }
+    }
+
+    fn names(outbox: &Outbox) -> Vec<Vec<String>> {
+        let entries = outbox.entries().unwrap();
+        let targets = entries.into_iter().map(|(_, pending)| pending.targets);
+        targets
+            .map(|targets| targets.into_iter().map(|t| t.name).collect())
+            .collect()
+    }
+
+    fn temp_outbox() -> Outbox {
+        let dir =
+            std::env::temp_dir().join(format!("notif_stopped-test-{:016x}", fastrand::u64(..)));
+        Outbox { dir }
+    }
+
+    #[test]
+    fn keeps_targets_that_are_not_configured() {
+        let outbox = temp_outbox();
+        let event = StopEvent::example(ExitReason::Exited(0));
+        outbox.save(&event, vec![target("a"), target("b")]).unwrap();
+
+        // Only `a` is configured.
+        let flushed = outbox
+            .flush(|_, pending| Ok(pending.iter().filter(|t| t.name != "a").cloned().collect()))
+            .unwrap();
+        assert_eq!((flushed.sent, flushed.kept), (0, 1));
+        assert_eq!(names(&outbox), [["b"]]);
+
+        // Nothing is configured.
+        let flushed = outbox.flush(|_, pending| Ok(pending.to_vec())).unwrap();
+        assert_eq!((flushed.sent, flushed.kept), (0, 1));
+        assert_eq!(names(&outbox), [["b"]]);
+
+        let flushed = outbox.flush(|_, _| Ok(Vec::new())).unwrap();
+        assert_eq!((flushed.sent, flushed.kept), (1, 0));
+        assert!(names(&outbox).is_empty());
+
+        std::fs::remove_dir_all(&outbox.dir).unwrap();
+    }
+
+    #[test]
+    fn stops_at_the_first_failure() {
+        let outbox = temp_outbox();
+        let mut event = StopEvent::example(ExitReason::Exited(1));
+        outbox.save(&event, vec![target("a"), target("b")]).unwrap();
+        event.stopped_at += std::time::Duration::from_secs(1);
+        outbox.save(&event, vec![target("a")]).unwrap();
+
+        let mut calls = 0;
+        let result = outbox.flush(|_, _| {
+            calls += 1;
+            Err((vec![target("b")], "b failed".to_owned()))
+        });
+        assert!(matches!(result, Err((0, e)) if e == "b failed"));
+        assert_eq!(calls, 1);
+        assert_eq!(names(&outbox), [vec!["b"], vec!["a"]]);
+
+        std::fs::remove_dir_all(&outbox.dir).unwrap();
+    }
+}
@@ -0,0 +1,593 @@
+use std::time::Duration;
+
+use regex::Regex;
+use sysinfo::{Pid, Process, ProcessRefreshKind, RefreshKind, System, UpdateKind};
+
+#[cfg(target_os = "linux")]
+use crate::event::ExitReason;
+
+/// A specific process.
+#[derive(Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub name: String,
+    /// When the process started, in seconds since the unix epoch.
+    pub start_time: u64,
+    pub cmd: Vec<String>,
+}
+
+impl ProcessInfo {
+    fn new(process: &Process) -> Self {
+        Self {
+            pid: process.pid(),
+            name: process.name().to_owned(),
+            start_time: process.start_time(),
+            cmd: process.cmd().to_vec(),
+        }
+    }
+
+    /// Whether both refer to the same process. A process with the same pid but a different name
+    /// or start time is a different process that was given a recycled pid.
+    fn is(&self, other: &ProcessInfo) -> bool {
+        self.pid == other.pid && self.name == other.name && self.start_time == other.start_time
+    }
+
+    /// Whether this process is in `s` (and not just something with the same pid).
+    fn is_in(&self, s: &impl ProcessSource) -> bool {
+        s.identity(self.pid)
+            .is_some_and(|(name, start_time)| name == self.name && start_time == self.start_time)
+    }
+}
+
+/// Where running processes are looked up, which is a `System` outside of tests.
+trait ProcessSource {
+    /// The name and start time of the process with the pid, if there's one running.
+    fn identity(&self, pid: Pid) -> Option<(&str, u64)>;
+}
+
+impl ProcessSource for System {
+    fn identity(&self, pid: Pid) -> Option<(&str, u64)> {
+        self.process(pid).map(|p| (p.name(), p.start_time()))
+    }
+}
+
+/// Criteria used to pick the process to watch. A process has to match every criterion that's set.
+#[derive(Default)]
+pub struct Selector {
+    pub pid: Option<Pid>,
+    /// Exact process name.
+    pub name: Option<String>,
+    pub name_regex: Option<Regex>,
+    /// Glob matched against the full path of the process's executable.
+    pub exe_glob: Option<glob::Pattern>,
+    /// Regex matched against the process's arguments, joined with spaces.
+    pub cmdline_regex: Option<Regex>,
+}
+
+impl Selector {
+    /// Whether the process satisfies all of the criteria.
+    pub fn matches(&self, process: &Process) -> bool {
+        self.pid.is_none_or(|pid| process.pid() == pid)
+            && self.name.as_ref().is_none_or(|name| process.name() == name)
+            && self
+                .name_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(process.name()))
+            && self
+                .exe_glob
+                .as_ref()
+                .is_none_or(|glob| process.exe().is_some_and(|exe| glob.matches_path(exe)))
+            && self
+                .cmdline_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(&process.cmd().join(" ")))
+    }
+
+    /// Finds the single process that matches.
+    ///
+    /// Errors if nothing matches or if the match is ambiguous (in which case all of the candidates
+    /// are listed).
+    pub fn find_process(&self) -> Result<ProcessInfo, String> {
+        let mut processes = self.find_processes()?;
+        if processes.len() == 1 {
+            return Ok(processes.remove(0));
+        }
+
+        let list = processes
+            .iter()
+            .map(|p| format!("\n  {} {} ({})", p.pid, p.name, p.cmd.join(" ")))
+            .collect::<String>();
+        Err(format!(
+            "{} processes match, use `--pid`, `--all`, `--any` or narrow the match:{list}",
+            processes.len()
+        ))
+    }
+
+    /// Finds every process that matches (sorted by pid).
+    ///
+    /// Errors if nothing matches.
+    pub fn find_processes(&self) -> Result<Vec<ProcessInfo>, String> {
+        let processes = self.matching(&new_system());
+        if processes.is_empty() {
+            return Err(match (self.pid, &self.name) {
+                (Some(pid), _) => format!("no matching process is running with pid {pid}"),
+                (None, Some(name)) => format!("process isn't running: {name}"),
+                (None, None) => "no running process matches".to_owned(),
+            });
+        }
+        Ok(processes)
+    }
+
+    /// The processes in `s` that match, sorted by pid. Threads and this process itself are never
+    /// considered.
+    fn matching(&self, s: &System) -> Vec<ProcessInfo> {
+        let own_pid = sysinfo::get_current_pid().ok();
+        let mut processes: Vec<_> = s
+            .processes()
+            .values()
+            .filter(|p| p.thread_kind().is_none() && Some(p.pid()) != own_pid)
+            .filter(|p| self.matches(p))
+            .map(ProcessInfo::new)
+            .collect();
+        processes.sort_by_key(|p| p.pid);
+        processes
+    }
+}
+
+/// A `System` with every process's name, command line and executable loaded.
+fn new_system() -> System {
+    System::new_with_specifics(RefreshKind::new().with_processes(process_refresh_kind()))
+}
+
+fn process_refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new()
+        .with_cmd(UpdateKind::OnlyIfNotSet)
+        .with_exe(UpdateKind::OnlyIfNotSet)
+}
+
+/// When to stop waiting on a set of processes.
+#[derive(Clone, Copy)]
+pub enum Until {
+    /// Wait until every process has exited.
+    AllExited,
+    /// Wait until at least one of the processes has exited.
+    AnyExited,
+}
+
+/// The state of the watched processes once waiting has finished.
+pub struct WatchSummary {
+    pub exited: Vec<ProcessInfo>,
+    pub still_running: Vec<ProcessInfo>,
+}
+
+impl WatchSummary {
+    /// A short description of which processes stopped, e.g. `cc1plus (pid 123)` or
+    /// `2 of 8 processes (cc1plus, ld)`.
+    pub fn describe(&self) -> String {
+        match (self.exited.as_slice(), self.still_running.is_empty()) {
+            ([ProcessInfo { pid, name, .. }], true) => format!("{name} (pid {pid})"),
+            (exited, _) => {
+                let mut names: Vec<&str> = exited.iter().map(|p| p.name.as_str()).collect();
+                names.sort_unstable();
+                names.dedup();
+                format!(
+                    "{} of {} processes ({})",
+                    exited.len(),
+                    exited.len() + self.still_running.len(),
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// A process that's being watched.
+struct Watched {
+    info: ProcessInfo,
+    /// Lets the process's exit be waited on directly, if the platform supports it.
+    pidfd: Option<pidfd::PidFd>,
+}
+
+impl Watched {
+    /// Starts watching the process. `s` is refreshed to make sure the pidfd (if any) refers to the
+    /// right process, since the pid could have been recycled since the process was found.
+    fn new(info: ProcessInfo, s: &mut System) -> Self {
+        let mut pidfd = pidfd::PidFd::open(info.pid);
+        if pidfd.is_some() {
+            s.refresh_pids(&[info.pid]);
+            if !info.is_in(s) {
+                // Without a pidfd, the process will be seen as having exited.
+                pidfd = None;
+            }
+        }
+        Self { info, pidfd }
+    }
+
+    /// Whether the process is still running. `s` needs to be up to date if there's no pidfd.
+    fn is_running(&self, s: &impl ProcessSource) -> bool {
+        match &self.pidfd {
+            Some(pidfd) => !pidfd.has_exited(),
+            None => self.info.is_in(s),
+        }
+    }
+}
+
+/// Blocks while the processes are running, until the `until` condition is satisfied.
+///
+/// On Linux, this waits on the processes' pidfds so their exits are noticed right away. Otherwise
+/// (or on kernels without pidfds), whether or not the processes are running will be checked every
+/// `check_interval` duration. If `rescan` is given, processes that start matching it later on are
+/// added to the watched set, which is also done every `check_interval` duration.
+pub fn block_while_processes_running(
+    processes: Vec<ProcessInfo>,
+    until: Until,
+    rescan: Option<&Selector>,
+    check_interval: Duration,
+) -> WatchSummary {
+    let mut s = System::new();
+    let mut running: Vec<Watched> = processes
+        .into_iter()
+        .map(|info| Watched::new(info, &mut s))
+        .collect();
+    let mut exited: Vec<ProcessInfo> = Vec::new();
+
+    loop {
+        match rescan {
+            Some(selector) => {
+                s.refresh_processes_specifics(process_refresh_kind());
+                for process in selector.matching(&s) {
+                    let known = running.iter().any(|w| w.info.is(&process))
+                        || exited.iter().any(|p| p.is(&process));
+                    if !known {
+                        running.push(Watched::new(process, &mut s));
+                    }
+                }
+            }
+            None => {
+                let polled: Vec<Pid> = running
+                    .iter()
+                    .filter(|w| w.pidfd.is_none())
+                    .map(|w| w.info.pid)
+                    .collect();
+                if !polled.is_empty() {
+                    s.refresh_pids(&polled);
+                }
+            }
+        }
+
+        if check_exits(&mut running, &mut exited, until, &s) {
+            return WatchSummary {
+                exited,
+                still_running: running.into_iter().map(|w| w.info).collect(),
+            };
+        }
+
+        let pidfds: Vec<&pidfd::PidFd> = running.iter().filter_map(|w| w.pidfd.as_ref()).collect();
+        if pidfds.is_empty() {
+            std::thread::sleep(check_interval);
+        } else {
+            // Only wake up on a timer if there's something that has to be checked manually.
+            let needs_polling = rescan.is_some() || pidfds.len() < running.len();
+            pidfd::wait_any(&pidfds, needs_polling.then_some(check_interval));
+        }
+    }
+}
+
+/// Moves the processes that have exited from `running` to `exited`, returning whether that
+/// satisfies `until`. `s` needs to be up to date for the processes without a pidfd.
+fn check_exits(
+    running: &mut Vec<Watched>,
+    exited: &mut Vec<ProcessInfo>,
+    until: Until,
+    s: &impl ProcessSource,
+) -> bool {
+    let (still_running, stopped): (Vec<_>, Vec<_>) = std::mem::take(running)
+        .into_iter()
+        .partition(|w| w.is_running(s));
+    *running = still_running;
+    exited.extend(stopped.into_iter().map(|w| w.info));
+
+    match until {
+        Until::AllExited => running.is_empty(),
+        Until::AnyExited => !exited.is_empty(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod pidfd {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    use sysinfo::Pid;
+
+    /// A file descriptor referring to a process, which becomes readable once the process exits.
+    pub struct PidFd(OwnedFd);
+
+    impl PidFd {
+        /// Returns `None` if the kernel doesn't support pidfds (or the process is already gone).
+        pub fn open(pid: Pid) -> Option<Self> {
+            // SAFETY: `pidfd_open` doesn't touch memory in this process.
+            let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_u32() as libc::pid_t, 0) };
+            // SAFETY: a non-negative return value is a new fd that nothing else owns.
+            (fd >= 0).then(|| Self(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) }))
+        }
+
+        pub fn has_exited(&self) -> bool {
+            wait_any(&[self], Some(Duration::ZERO))
+        }
+    }
+
+    /// Blocks until at least one of the processes has exited or the timeout (if any) has passed.
+    /// Returns whether any of the processes have exited.
+    pub fn wait_any(pidfds: &[&PidFd], timeout: Option<Duration>) -> bool {
+        let mut fds: Vec<libc::pollfd> = pidfds
+            .iter()
+            .map(|pidfd| libc::pollfd {
+                fd: pidfd.0.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(libc::c_int::MAX as u128) as _);
+
+        // SAFETY: `fds` is a valid array of `fds.len()` pollfds.
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        n > 0 && fds.iter().any(|fd| fd.revents != 0)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod pidfd {
+    use std::time::Duration;
+
+    use sysinfo::Pid;
+
+    /// Pidfds are Linux-only, so this can never be constructed elsewhere.
+    pub enum PidFd {}
+
+    impl PidFd {
+        pub fn open(_pid: Pid) -> Option<Self> {
+            None
+        }
+
+        pub fn has_exited(&self) -> bool {
+            match *self {}
+        }
+    }
+
+    pub fn wait_any(_pidfds: &[&PidFd], timeout: Option<Duration>) -> bool {
+        // There can't be any pidfds, so there's nothing to do but wait.
+        if let Some(timeout) = timeout {
+            std::thread::sleep(timeout);
+        }
+        false
+    }
+}
+
+/// Attaches to the process with ptrace and blocks until it exits, returning how it exited.
+///
+/// This needs permission to trace the process (see `/proc/sys/kernel/yama/ptrace_scope`). Signals
+/// the process receives while being traced are passed through to it unchanged. If the process
+/// exited before it was attached to and its pid was recycled, this detaches from the new process
+/// and returns an unknown exit reason.
+#[cfg(target_os = "linux")]
+pub fn trace_until_exit(process: &ProcessInfo) -> Result<ExitReason, String> {
+    use std::ptr::null_mut;
+
+    let pid = process.pid.as_u32() as libc::pid_t;
+    // SAFETY: `PTRACE_SEIZE` doesn't touch memory in this process.
+    if unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid, null_mut::<libc::c_void>(), 0) } == -1 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("failed to attach to process {pid}: {e}"));
+    }
+
+    // The pid could have been recycled between finding the process and attaching to it. The
+    // process can't be reaped while it's attached to, so this check can't be raced in turn.
+    let mut s = System::new();
+    s.refresh_pids(&[process.pid]);
+    if !process.is_in(&s) {
+        detach(pid);
+        return Ok(ExitReason::Unknown);
+    }
+
+    loop {
+        let mut status = 0;
+        // SAFETY: `status` is a valid place for `waitpid` to write to.
+        if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(format!("failed to wait on process {pid}: {e}"));
+        }
+
+        if libc::WIFEXITED(status) {
+            return Ok(ExitReason::Exited(libc::WEXITSTATUS(status)));
+        }
+        if libc::WIFSIGNALED(status) {
+            return Ok(ExitReason::Signaled {
+                signal: libc::WTERMSIG(status),
+                core_dumped: libc::WCOREDUMP(status),
+            });
+        }
+        if libc::WIFSTOPPED(status) {
+            // Group-stops (e.g. from ^Z) have to be left in place with `PTRACE_LISTEN` until the
+            // process is continued, which is reported as a `SIGTRAP` event-stop. Anything else is a
+            // signal that's about to be delivered and needs to be passed on.
+            let signal = libc::WSTOPSIG(status);
+            let (request, signal) = match (status >> 16 == libc::PTRACE_EVENT_STOP, signal) {
+                (true, libc::SIGTRAP) => (libc::PTRACE_CONT, 0),
+                (true, _) => (libc::PTRACE_LISTEN, 0),
+                (false, signal) => (libc::PTRACE_CONT, signal),
+            };
+            // SAFETY: the tracee is stopped, and these requests don't touch our memory.
+            unsafe { libc::ptrace(request, pid, null_mut::<libc::c_void>(), signal) };
+        }
+    }
+}
+
+/// Detaches from a (seized and running) tracee, which has to be stopped first.
+#[cfg(target_os = "linux")]
+fn detach(pid: libc::pid_t) {
+    use std::ptr::null_mut;
+
+    // SAFETY: none of these touch memory in this process except for `waitpid`, which writes to
+    // `status`.
+    unsafe {
+        libc::ptrace(libc::PTRACE_INTERRUPT, pid, null_mut::<libc::c_void>(), 0);
+        let mut status = 0;
+        if libc::waitpid(pid, &mut status, libc::__WALL) == -1 || !libc::WIFSTOPPED(status) {
+            return;
+        }
+        // A signal that was about to be delivered is passed on rather than dropped.
+        let signal = match status >> 16 {
+            0 => libc::WSTOPSIG(status),
+            _ => 0,
+        };
+        libc::ptrace(libc::PTRACE_DETACH, pid, null_mut::<libc::c_void>(), signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Processes by pid, as `(name, start time)`.
+    struct MockSource(HashMap<Pid, (&'static str, u64)>);
+
+    impl ProcessSource for MockSource {
+        fn identity(&self, pid: Pid) -> Option<(&str, u64)> {
+            self.0.get(&pid).copied()
+        }
+    }
+
+    fn watched(pid: usize, name: &str, start_time: u64) -> Watched {
+        Watched {
+            info: ProcessInfo {
+                pid: Pid::from(pid),
+                name: name.to_owned(),
+                start_time,
+                cmd: Vec::new(),
+            },
+            pidfd: None,
+        }
+    }
+
+    #[test]
+    fn recycled_pids_count_as_exited() {
+        let source = MockSource(HashMap::from([
+            (Pid::from(10), ("make", 100)),
+            // These pids were recycled by a different process.
+            (Pid::from(11), ("make", 200)),
+            (Pid::from(12), ("bash", 100)),
+        ]));
+
+        assert!(watched(10, "make", 100).is_running(&source));
+        assert!(!watched(11, "make", 100).is_running(&source));
+        assert!(!watched(12, "make", 100).is_running(&source));
+        assert!(!watched(13, "make", 100).is_running(&source));
+    }
+
+    #[test]
+    fn same_process_needs_the_same_pid_name_and_start_time() {
+        let process = watched(10, "make", 100).info;
+        assert!(process.is(&watched(10, "make", 100).info));
+        assert!(!process.is(&watched(11, "make", 100).info));
+        assert!(!process.is(&watched(10, "cargo", 100).info));
+        assert!(!process.is(&watched(10, "make", 101).info));
+    }
+
+    /// Watches make, cc and ld, returning the processes left running, the ones that exited and
+    /// whether `until` was satisfied after each of them exits in turn.
+    fn watch_until(until: Until) -> Vec<(Vec<ProcessInfo>, Vec<ProcessInfo>, bool)> {
+        let mut source = MockSource(HashMap::from([
+            (Pid::from(10), ("make", 100)),
+            (Pid::from(11), ("cc", 100)),
+            (Pid::from(12), ("ld", 100)),
+        ]));
+        let mut running = vec![
+            watched(10, "make", 100),
+            watched(11, "cc", 100),
+            watched(12, "ld", 100),
+        ];
+        let mut exited = Vec::new();
+        let mut checks = Vec::new();
+
+        for pid in [None, Some(11), Some(12), Some(10)] {
+            if let Some(pid) = pid {
+                source.0.remove(&Pid::from(pid));
+            }
+            let done = check_exits(&mut running, &mut exited, until, &source);
+            let still_running = running.iter().map(|w| w.info.clone()).collect();
+            checks.push((still_running, exited.clone(), done));
+        }
+        checks
+    }
+
+    #[test]
+    fn any_exited_stops_after_the_first_exit() {
+        let checks = watch_until(Until::AnyExited);
+        let done: Vec<bool> = checks.iter().map(|(_, _, done)| *done).collect();
+        assert_eq!(done, [false, true, true, true]);
+
+        let (still_running, exited, _) = checks[1].clone();
+        let summary = WatchSummary {
+            exited,
+            still_running,
+        };
+        assert_eq!(summary.describe(), "1 of 3 processes (cc)");
+    }
+
+    #[test]
+    fn all_exited_waits_for_every_process() {
+        let checks = watch_until(Until::AllExited);
+        let done: Vec<bool> = checks.iter().map(|(_, _, done)| *done).collect();
+        assert_eq!(done, [false, false, false, true]);
+
+        let (still_running, exited, _) = checks[2].clone();
+        assert_eq!(still_running.len(), 1);
+        let summary = WatchSummary {
+            exited,
+            still_running,
+        };
+        assert_eq!(summary.describe(), "2 of 3 processes (cc, ld)");
+
+        let (still_running, exited, _) = checks[3].clone();
+        let summary = WatchSummary {
+            exited,
+            still_running,
+        };
+        assert_eq!(summary.describe(), "3 of 3 processes (cc, ld, make)");
+    }
+
+    #[test]
+    fn a_single_process_is_described_by_name() {
+        let summary = WatchSummary {
+            exited: vec![watched(10, "make", 100).info],
+            still_running: Vec::new(),
+        };
+        assert_eq!(summary.describe(), "make (pid 10)");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tracing_a_recycled_pid_detaches() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.5")
+            .spawn()
+            .unwrap();
+        let pid = Pid::from_u32(child.id());
+        let mut process = new_system().process(pid).map(ProcessInfo::new).unwrap();
+
+        let start_time = process.start_time;
+        process.start_time -= 1;
+        assert!(trace_until_exit(&process) == Ok(ExitReason::Unknown));
+
+        // It can only be attached to again if it was detached from.
+        process.start_time = start_time;
+        assert!(trace_until_exit(&process) == Ok(ExitReason::Exited(0)));
+        drop(child.wait());
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use crate::event::StopEvent;
+
+/// The placeholders that can be used in a template, as `{{name}}`. All of them except `duration`
+/// are fields of the JSON body.
+const PLACEHOLDERS: &[&str] = &[
+    "description",
+    "process_name",
+    "pid",
+    "command_line",
+    "hostname",
+    "started_at",
+    "stopped_at",
+    "runtime_secs",
+    "duration",
+    "exit_reason",
+    "exit_code",
+    "signal",
+    "core_dumped",
+];
+
+/// A user-defined webhook body, with `{{placeholder}}`s that are filled in from the stop event.
+///
+/// Templates with a `.json` extension are sent as JSON, with values escaped so that they can go in
+/// JSON strings. Unknown values (e.g. the exit code of a process that wasn't traced) become `null`
+/// there, and a placeholder that's a whole string (like `"{{signal}}"`) becomes `null` with its
+/// quotes, so the body stays valid JSON. Other templates are sent as plain text, with values
+/// inserted as-is and unknown values left empty.
+pub struct Template {
+    text: String,
+    json: bool,
+}
+
+impl Template {
+    /// Reads a template from a file, making sure it only uses known placeholders.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read template {}: {e}", path.display()))?;
+
+        for placeholder in placeholders(&text) {
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "unknown placeholder in template: {{{{{placeholder}}}}} (expected one of: {})",
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+        }
+
+        let json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        Ok(Self { text, json })
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        if self.json {
+            "application/json"
+        } else {
+            "text/plain; charset=utf-8"
+        }
+    }
+
+    /// Fills in the placeholders. This is done in a single pass, so placeholders in values are
+    /// left alone.
+    pub fn render(&self, event: &StopEvent) -> String {
+        let mut values = event.fields();
+        values.push(("duration", event.duration()));
+
+        let mut rendered = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+
+            let value = rest.split_once("}}").and_then(|(name, after)| {
+                let (_, value) = values
+                    .iter()
+                    .find(|(placeholder, _)| *placeholder == name)?;
+                Some((value.as_deref(), after))
+            });
+            match value {
+                Some((Some(value), after)) => {
+                    if self.json {
+                        rendered.push_str(&escape_json(value));
+                    } else {
+                        rendered.push_str(value);
+                    }
+                    rest = after;
+                }
+                Some((None, after)) if self.json => {
+                    rest = after;
+                    if rendered.ends_with('"') && rest.starts_with('"') {
+                        rendered.pop();
+                        rest = &rest[1..];
+                    }
+                    rendered.push_str("null");
+                }
+                Some((None, after)) => rest = after,
+                None => rendered.push_str("{{"),
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// Escapes a value to go in a JSON string (without adding the quotes).
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+/// The names of the `{{placeholder}}`s in the text.
+fn placeholders(text: &str) -> impl Iterator<Item = &str> {
+    text.split("{{")
+        .skip(1)
+        .filter_map(|s| s.split_once("}}"))
+        .map(|(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ExitReason;
+
+    fn event() -> StopEvent {
+        let mut event = StopEvent::example(ExitReason::Exited(0));
+        event.command_line = Some(
+            ["python", "-c", "print(\"{{pid}}\")"]
+                .map(str::to_owned)
+                .to_vec(),
+        );
+        event
+    }
+
+    #[test]
+    fn json_templates_are_escaped() {
+        let template = Template {
+            text: r#"{"cmd": "{{command_line}}", "pid": {{pid}}, "took": "{{duration}}"}"#
+                .to_owned(),
+            json: true,
+        };
+        let rendered = template.render(&event());
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(value["cmd"], r#"python -c print("{{pid}}")"#);
+        assert_eq!(value["pid"], 4242);
+        assert_eq!(value["took"], "1m 30s");
+        assert_eq!(template.content_type(), "application/json");
+    }
+
+    #[test]
+    fn unknown_values_are_null_in_json_templates() {
+        let template = Template {
+            text: r#"{"code": {{exit_code}}, "signal": "{{signal}}", "text": "signal {{signal}}"}"#
+                .to_owned(),
+            json: true,
+        };
+        let rendered = template.render(&event());
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(value["code"], 0);
+        assert_eq!(value["signal"], serde_json::Value::Null);
+        assert_eq!(value["text"], "signal null");
+    }
+
+    #[test]
+    fn text_templates_are_not_escaped() {
+        let template = Template {
+            text: "{{process_name}} ({{{{pid}}) ran {{command_line}}, {{signal}}{{".to_owned(),
+            json: false,
+        };
+        assert_eq!(
+            template.render(&event()),
+            r#"make ({{4242) ran python -c print("{{pid}}"), {{"#
+        );
+        assert_eq!(template.content_type(), "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        let path =
+            std::env::temp_dir().join(format!("notif_stopped-test-{}.json", fastrand::u64(..)));
+        std::fs::write(&path, "{{pid}} {{nope}}").unwrap();
+        let result = Template::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err_and(|e| e.starts_with("unknown placeholder in template: {{nope}}")));
+    }
+}